@@ -0,0 +1,346 @@
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use crate::env::warehouse::high_fidelity_warehouse::{Point, TaskActionPair};
+use crate::scenario::Scenario;
+use crate::scheduler::evaluation::Evaluation;
+use crate::scheduler::simulator::SimulationReport;
+
+/*
+The intermediate artifacts of the warehouse pipeline, each stage reads the artifacts of the stages
+before it from the checkpoint directory $SCPM_HOME/pipeline/v{CHECKPOINT_VERSION} and writes its own:
+build-scpm -> setup.json (the SCPM matrices are written by the SCPM itself)
+solve      -> mus.json as soon as the multi-objective solve finishes, then solution.json
+allocate   -> allocation.json
+plan       -> plan.json, $SCPM_HOME/schedulers/map_{agent}_{task}.bin
+regen      -> $SCPM_HOME/schedulers/regen_{agent}.bin
+Schedulers are written in the binary format of scheduler::binary, with --json also as .json next to
+them.
+simulate   -> simulation.json
+evaluate   -> evaluation.json
+*/
+
+/// The version of the artifact formats, bumped whenever an artifact changes so that checkpoints
+/// written by an older version are never resumed
+pub const CHECKPOINT_VERSION: u32 = 7;
+
+/// The checkpointed stages in pipeline order, `imovi` is the multi-objective solve within `solve`
+pub const STAGES: [&str; 8] = ["build-scpm", "imovi", "solve", "allocate", "plan", "regen", "simulate", "evaluate"];
+
+/// A decoded scheduler, keyed by agent position then agent direction
+pub type DecodedScheduler = std::collections::HashMap<String, std::collections::HashMap<String, Vec<TaskActionPair>>>;
+
+#[derive(Debug)]
+pub enum ArtifactError {
+    /// SCPM_HOME is not set
+    MissingHome,
+    Io(PathBuf, std::io::Error),
+    Json(PathBuf, String),
+    /// An artifact which a stage depends on has not been written, holds the artifact and the
+    /// stage which writes it
+    Missing(&'static str, &'static str),
+    /// The checkpoint was written by a different version, holds the version found
+    Version(u32),
+    /// The checkpoint was written for a different scenario, holds the checkpoint and scenario hashes
+    ScenarioMismatch(u64, u64)
+}
+
+impl fmt::Display for ArtifactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArtifactError::MissingHome => { write!(f, "the SCPM_HOME environment variable is not set") }
+            ArtifactError::Io(p, e) => { write!(f, "unable to access {}: {}", p.display(), e) }
+            ArtifactError::Json(p, e) => { write!(f, "unable to read or write {}: {}", p.display(), e) }
+            ArtifactError::Missing(name, stage) => {
+                write!(f, "{} has not been written, run the {} stage first", name, stage)
+            }
+            ArtifactError::Version(v) => {
+                write!(f, "the checkpoint has version {}, expected version {}", v, CHECKPOINT_VERSION)
+            }
+            ArtifactError::ScenarioMismatch(checkpoint, scenario) => {
+                write!(f, "the checkpoint was written for scenario {:016x} but the scenario is {:016x}, \
+                    run without --resume to start again", checkpoint, scenario)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArtifactError { }
+
+fn scpm_home() -> Result<PathBuf, ArtifactError> {
+    std::env::var("SCPM_HOME")
+        .map(PathBuf::from)
+        .map_err(|_| ArtifactError::MissingHome)
+}
+
+fn ensure_dir(dir: PathBuf) -> Result<PathBuf, ArtifactError> {
+    fs::create_dir_all(&dir).map_err(|e| ArtifactError::Io(dir.clone(), e))?;
+    Ok(dir)
+}
+
+/// $SCPM_HOME/pipeline/v{CHECKPOINT_VERSION}, created if it does not exist
+pub fn artifact_dir() -> Result<PathBuf, ArtifactError> {
+    ensure_dir(scpm_home()?.join("pipeline").join(format!("v{}", CHECKPOINT_VERSION)))
+}
+
+/// $SCPM_HOME/schedulers, created if it does not exist
+pub fn scheduler_dir() -> Result<PathBuf, ArtifactError> {
+    ensure_dir(scpm_home()?.join("schedulers"))
+}
+
+pub fn task_scheduler_path(agent: i32, task: i32) -> Result<PathBuf, ArtifactError> {
+    Ok(scheduler_dir()?.join(format!("map_{}_{}.bin", agent, task)))
+}
+
+pub fn regen_scheduler_path(agent: usize) -> Result<PathBuf, ArtifactError> {
+    Ok(scheduler_dir()?.join(format!("regen_{}.bin", agent)))
+}
+
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), ArtifactError> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)
+        .map_err(|e| ArtifactError::Io(path.to_path_buf(), e))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), value)
+        .map_err(|e| ArtifactError::Json(path.to_path_buf(), e.to_string()))
+}
+
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, ArtifactError> {
+    let file = fs::File::open(path).map_err(|e| ArtifactError::Io(path.to_path_buf(), e))?;
+    serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| ArtifactError::Json(path.to_path_buf(), e.to_string()))
+}
+
+/// An artifact stored in the pipeline directory
+pub trait Artifact: Serialize + DeserializeOwned {
+    /// The file name of the artifact
+    const NAME: &'static str;
+    /// The stage which writes the artifact
+    const STAGE: &'static str;
+
+    fn path() -> Result<PathBuf, ArtifactError> {
+        Ok(artifact_dir()?.join(Self::NAME))
+    }
+
+    fn exists() -> bool {
+        Self::path().map(|p| p.exists()).unwrap_or(false)
+    }
+
+    fn save(&self) -> Result<PathBuf, ArtifactError> {
+        let path = Self::path()?;
+        write_json(&path, self)?;
+        Ok(path)
+    }
+
+    fn load() -> Result<Self, ArtifactError> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Err(ArtifactError::Missing(Self::NAME, Self::STAGE))
+        }
+        read_json(&path)
+    }
+}
+
+/// The stages completed for a scenario, identified by `Scenario::fingerprint`, each with the
+/// `Scenario::stage_fingerprint` it completed with. A stage is only skipped on resume if the
+/// checkpoint version, the scenario hash and the hash of the stage match.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub scenario_hash: u64,
+    pub completed: Vec<(String, u64)>
+}
+
+impl Artifact for Checkpoint {
+    const NAME: &'static str = "checkpoint.json";
+    const STAGE: &'static str = "build-scpm";
+}
+
+/// The fingerprint of `scenario`, failing if its layout file can not be read
+fn fingerprint(scenario: &Scenario) -> Result<u64, ArtifactError> {
+    scenario.fingerprint()
+        .map_err(|e| ArtifactError::Io(PathBuf::from(scenario.layout.as_deref().unwrap_or_default()), e))
+}
+
+impl Checkpoint {
+    /// Load the checkpoint, checking that it was written by this version for `scenario`
+    pub fn resume(scenario: &Scenario) -> Result<Checkpoint, ArtifactError> {
+        let checkpoint = Checkpoint::load()?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(ArtifactError::Version(checkpoint.version))
+        }
+        let hash = fingerprint(scenario)?;
+        if checkpoint.scenario_hash != hash {
+            return Err(ArtifactError::ScenarioMismatch(checkpoint.scenario_hash, hash))
+        }
+        Ok(checkpoint)
+    }
+
+    /// Whether `stage` completed with the command line parameters of `scenario`
+    pub fn is_complete(&self, stage: &str, scenario: &Scenario) -> bool {
+        let hash = scenario.stage_fingerprint(stage);
+        self.completed.iter().any(|(s, h)| s == stage && *h == hash)
+    }
+
+    /// Record that `stage` completed for `scenario`. The stages after `stage` are no longer
+    /// complete as their inputs have changed, and `build-scpm` starts a new checkpoint.
+    pub fn complete(stage: &str, scenario: &Scenario) -> Result<(), ArtifactError> {
+        let order = STAGES.iter().position(|s| *s == stage);
+        let mut completed: Vec<(String, u64)> = match Checkpoint::load() {
+            Ok(checkpoint) if stage != STAGES[0] && checkpoint.version == CHECKPOINT_VERSION => {
+                checkpoint.completed
+            }
+            _ => { Vec::new() }
+        };
+        completed.retain(|(s, _)| STAGES.iter().position(|x| x == s) < order);
+        completed.push((stage.to_string(), scenario.stage_fingerprint(stage)));
+        Checkpoint {
+            version: CHECKPOINT_VERSION,
+            scenario_hash: fingerprint(scenario)?,
+            completed
+        }.save()?;
+        Ok(())
+    }
+}
+
+/// The experiment set up by `build-scpm`: the scenario and the random choices made from it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SetupArtifact {
+    pub scenario: Scenario,
+    pub task_racks: Vec<usize>, // index into the rack positions of each task
+    pub task_feeds: Vec<usize>, // index into the feed points of each task
+    pub agent_starts: Vec<Point>,
+    pub queue_points: Vec<Point>,
+    /// The initial product state index of each (agent, task)
+    pub initial_states: Vec<(i32, i32, usize)>,
+    pub scpm_states: usize,
+    pub scpm_transitions: usize
+}
+
+impl Artifact for SetupArtifact {
+    const NAME: &'static str = "setup.json";
+    const STAGE: &'static str = "build-scpm";
+}
+
+/// The scheduler of agent `agent` for task `task`, JSON objects can not have tuple keys
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MuEntry {
+    pub agent: i32,
+    pub task: i32,
+    pub scheduler: Vec<f64>
+}
+
+/// The schedulers of the multi-objective solve, one list of entries per extreme point, and the
+/// achievable target found by the solve
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MusArtifact {
+    pub mus: Vec<Vec<MuEntry>>,
+    pub tnew: Vec<f64>
+}
+
+impl Artifact for MusArtifact {
+    const NAME: &'static str = "mus.json";
+    const STAGE: &'static str = "solve";
+}
+
+impl MusArtifact {
+    pub fn from_mus(mus: &[hashbrown::HashMap<(i32, i32), Vec<f64>>], tnew: &[f64]) -> MusArtifact {
+        MusArtifact {
+            mus: mus.iter()
+                .map(|mu| {
+                    let mut entries: Vec<MuEntry> = mu.iter()
+                        .map(|((a, t), v)| MuEntry { agent: *a, task: *t, scheduler: v.to_vec() })
+                        .collect();
+                    entries.sort_by_key(|e| (e.agent, e.task));
+                    entries
+                })
+                .collect(),
+            tnew: tnew.to_vec()
+        }
+    }
+
+    pub fn to_mus(&self) -> Vec<hashbrown::HashMap<(i32, i32), Vec<f64>>> {
+        self.mus.iter()
+            .map(|mu| mu.iter().map(|e| ((e.agent, e.task), e.scheduler.to_vec())).collect())
+            .collect()
+    }
+}
+
+/// Agent `agent` is allocated task `task` by the scheduler of extreme point `k`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct TaskAllocation {
+    pub task: usize,
+    pub k: usize,
+    pub agent: usize
+}
+
+/// The outcome of the multi-objective solve and the randomised task witness
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SolutionArtifact {
+    pub tnew: Vec<f64>,
+    /// The agent allocated each task under each extreme point
+    pub allocations: Vec<TaskAllocation>,
+    /// The cost of each (agent, task, extreme point) allocation
+    pub costs: Vec<(i32, i32, i32, f64)>,
+    /// The probability of completing each (task, extreme point)
+    pub probs: Vec<(i32, i32, f64)>,
+    /// The weight of each extreme point, per task
+    pub weights: Vec<(i32, Vec<f64>)>
+}
+
+impl Artifact for SolutionArtifact {
+    const NAME: &'static str = "solution.json";
+    const STAGE: &'static str = "solve";
+}
+
+/// The task allocation sampled from the solution
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AllocationArtifact {
+    pub allocations: Vec<TaskAllocation>
+}
+
+impl Artifact for AllocationArtifact {
+    const NAME: &'static str = "allocation.json";
+    const STAGE: &'static str = "allocate";
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlanArtifact {
+    pub agent_costs: Vec<f64>,
+    /// The expected cost of each product scheduler as (agent, task, cost)
+    pub task_costs: Vec<(usize, usize, f64)>,
+    pub allocations_per_agent: Vec<Vec<usize>>
+}
+
+impl Artifact for PlanArtifact {
+    const NAME: &'static str = "plan.json";
+    const STAGE: &'static str = "plan";
+}
+
+/// The multi-agent simulation of the allocated task schedulers and the regeneration schedulers
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SimulationArtifact {
+    pub report: SimulationReport
+}
+
+impl Artifact for SimulationArtifact {
+    const NAME: &'static str = "simulation.json";
+    const STAGE: &'static str = "simulate";
+}
+
+/// The Monte Carlo estimates of every allocated task scheduler against the solver predictions
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EvaluationArtifact {
+    pub episodes: usize,
+    pub evaluations: Vec<Evaluation>
+}
+
+impl Artifact for EvaluationArtifact {
+    const NAME: &'static str = "evaluation.json";
+    const STAGE: &'static str = "evaluate";
+}
@@ -1,183 +1,431 @@
 #![allow(non_snake_case)]
 
-use std::fs::OpenOptions;
-use std::io::BufWriter;
 use hashbrown::HashMap;
 use indicatif::{ProgressBar, ProgressStyle};
 use rand::rngs::StdRng;
-use rand::{SeedableRng, thread_rng};
+use rand::SeedableRng;
 use rand::prelude::SliceRandom;
 use rand::seq::IteratorRandom;
 use scpm::algorithm::lp_solver::LPSolver;
-use scpm::dfa::definition::{DFA2, Data};
+use scpm::dfa::definition::{Data, DFA2};
 use scpm::agent::{Robot, MDPOps, Agent, serialise_state_mapping, make_serialised_state_map};
 use scpm::algorithm::motap_solver::{IMOVISolver, MultiObjSolver};
 use scpm::sparse_to_cs;
 use scpm::scpm::{definition::{SCPM}, matrix_ops::MatrixOps};
 use scpm::scpm::definition::SparseMatrixAttr;
 use scpm::solver::*;
-use rusty_robots::env::warehouse::low_fidelity_warehouse::{LowResEnv, LowResState, LowResWord};
-use rusty_robots::env::warehouse::high_fidelity_warehouse::{create_decoded_sched_to_file, front_pos,
-                                                            Info, Point, State, TaskActionPair, warehouse_defaults, WarehouseEnv, WarehouseWord};
+use rusty_robots::artifacts::{Artifact, AllocationArtifact, artifact_dir, Checkpoint, EvaluationArtifact, MusArtifact, PlanArtifact,
+                              SetupArtifact, SimulationArtifact, SolutionArtifact, TaskAllocation,
+                              regen_scheduler_path, task_scheduler_path};
+use rusty_robots::env::gym_env::{build_model, Env};
+use rusty_robots::env::reward::WarehouseRewardModel;
+use rusty_robots::env::warehouse::tasks::{hr_replenishment, lr_replenishment, regeneration, TaskDfa};
+use rusty_robots::error::Error;
+use rusty_robots::scheduler::binary::PolicyHeader;
+use rusty_robots::scheduler::evaluation::MonteCarlo;
+use rusty_robots::scheduler::executor::Executor;
+use rusty_robots::scheduler::export::{ExportAllocation, ExportLayout, PolicyExport};
+use rusty_robots::scheduler::policy::Policy;
+use rusty_robots::scheduler::simulator::{Job, MultiAgentSimulator, SimAgent};
+use rusty_robots::env::warehouse::layout::WarehouseLayout;
+use rusty_robots::env::warehouse::render::Overlay;
+use rusty_robots::scenario::{Scenario, ScenarioError, USAGE};
+use rusty_robots::env::warehouse::low_fidelity_warehouse::{LowResNoise, LowResState, LowResWord};
+use rusty_robots::env::warehouse::high_fidelity_warehouse::{Info, Point, State, warehouse_defaults, WarehouseWord};
 use num_cpus;
 
-const THREAD_COUNT_SAVE: usize = 30; // the number of threads to use in threadpools
-const THREAD_COUNT_LOAD: usize = 10;
+const COMMANDS: &str = "\
+commands:
+  build-scpm   construct the low fidelity products and the SCPM matrices, writes setup.json
+  solve        solve the SCPM and compute the randomised task witness, writes mus.json and solution.json
+  allocate     sample a task allocation from the solution, writes allocation.json
+  plan         synthesise high fidelity schedulers for the allocated tasks, writes plan.json
+  regen        synthesise the regeneration schedulers which return agents to their queue points
+  simulate     step all agents through their schedulers together on the high fidelity warehouse,
+               writes simulation.json
+  evaluate     sample trajectories of each allocated scheduler and compare the estimated cost and
+               success probability with the solver predictions, writes evaluation.json
+  inspect      summarise the artifacts written so far
+  render       draw the layout, the greedy trajectory of each allocated scheduler and the simulated run
+               as text and as SVG files in the artifact directory, and the simulated run as an animated
+               GIF when built with the gif feature
+  export       write the allocated task schedulers and the regeneration schedulers, with the layout and
+               the allocation, as JSON Lines to policies.jsonl in the artifact directory
+  run          every stage from build-scpm to regen (the default)
+
+  --resume     skip the stages recorded as complete in the checkpoint, the scenario must match the
+               one the checkpoint was written for. solve --resume reuses a finished multi-objective solve
+  --json       plan and regen also write each scheduler as JSON next to its binary file, for debugging
+
+Artifacts are stored under $SCPM_HOME/pipeline/v<version> and schedulers under $SCPM_HOME/schedulers. The
+stages after build-scpm take the agents, tasks, layout and seed from setup.json, and the targets,
+tolerances, allocation seed and thread counts from the command line.
+";
+
+/// The upper limit on the number of steps of a simulation
+const MAX_SIMULATION_STEPS: usize = 10_000;
+/// The number of consecutive steps without movement, while agents are blocked, that counts as a deadlock
+const DEADLOCK_STEPS: usize = 20;
+
+type StageResult = Result<(), Box<dyn std::error::Error>>;
 
 fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let command = if args.first().map_or(false, |a| !a.starts_with('-')) {
+        args.remove(0)
+    } else {
+        "run".to_string()
+    };
+    let resume = args.iter().any(|a| a == "--resume");
+    let json = args.iter().any(|a| a == "--json");
+    args.retain(|a| a != "--resume" && a != "--json");
+    let scenario = match Scenario::from_args(args) {
+        Ok(scenario) => { scenario }
+        Err(ScenarioError::Help(usage)) => {
+            println!("{}\n{}", usage, COMMANDS);
+            return
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let result = match command.as_str() {
+        "build-scpm" => { build_scpm(&scenario) }
+        "solve" => { solve(&scenario, resume) }
+        "allocate" => { allocate(&scenario) }
+        "plan" => { plan(&scenario, json) }
+        "regen" => { regen(&scenario, json) }
+        "simulate" => { simulate(&scenario) }
+        "evaluate" => { evaluate(&scenario) }
+        "inspect" => { inspect() }
+        "render" => { render(&scenario) }
+        "export" => { export(&scenario) }
+        "run" => { run(&scenario, resume, json) }
+        _ => {
+            eprintln!("unknown command '{}'\n\n{}\n{}", command, USAGE, COMMANDS);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("{} failed: {}", command, e);
+        std::process::exit(1);
+    }
+}
 
+/// Every stage from build-scpm to regen, skipping the stages completed in the checkpoint on resume.
+/// Once a stage runs, e.g. because its tolerance changed, every stage after it runs as well.
+fn run(scenario: &Scenario, resume: bool, json: bool) -> StageResult {
+    let mut checkpoint = if resume { Some(Checkpoint::resume(scenario)?) } else { None };
+    let mut skip = |stage: &str| {
+        let completed = checkpoint.as_ref().map_or(false, |c| c.is_complete(stage, scenario));
+        if completed {
+            println!("{} is complete in the checkpoint, skipping", stage);
+        } else {
+            checkpoint = None;
+        }
+        completed
+    };
+    if !skip("build-scpm") { build_scpm(scenario)?; }
+    if !skip("solve") { solve(scenario, resume)?; }
+    if !skip("allocate") { allocate(scenario)?; }
+    if !skip("plan") { plan(scenario, json)?; }
+    if !skip("regen") { regen(scenario, json)?; }
+    Ok(())
+}
+
+fn threads(requested: usize) -> usize {
     let cpus_available = num_cpus::get();
-    let cpus_used_save: usize = if cpus_available > THREAD_COUNT_SAVE { THREAD_COUNT_SAVE } else { cpus_available };
-    let cpus_used_load: usize = if cpus_available > THREAD_COUNT_LOAD { THREAD_COUNT_LOAD } else { cpus_available };
-    // Check file system has been created
+    if cpus_available > requested { requested } else { cpus_available }
+}
+
+/// The scenario of a stage after build-scpm: the structure of the experiment recorded in the
+/// setup, with the solver parameters of the command line
+fn stage_scenario(setup: &SetupArtifact, cli: &Scenario) -> Scenario {
+    Scenario {
+        allocation_seed: cli.allocation_seed,
+        cost_target: cli.cost_target,
+        prob_target: cli.prob_target,
+        eps: cli.eps,
+        hr_eps: cli.hr_eps,
+        threads_save: cli.threads_save,
+        threads_load: cli.threads_load,
+        episodes: cli.episodes,
+        ..setup.scenario.clone()
+    }
+}
+
+/// Storage for the warehouse `Info` of a scenario, and the reward model shared by the low and high
+/// fidelity warehouses
+struct Site {
+    layout: Option<WarehouseLayout>,
+    racks: Vec<Point>,
+    corridors: Vec<Point>,
+    rotation_mapping: HashMap<u8, (i32, i32)>,
+    feed_points: Vec<Point>,
+    rewards: WarehouseRewardModel
+}
 
-    let na: usize = 4;
-    let nt: usize = 9;
+impl Site {
+    fn load(scenario: &Scenario) -> Result<Site, Box<dyn std::error::Error>> {
+        let layout = match &scenario.layout {
+            Some(path) => { Some(WarehouseLayout::from_file(path)?) }
+            None => { None }
+        };
+        let (racks, corridors, rotation_mapping) = warehouse_defaults();
+        Ok(Site {
+            layout,
+            racks,
+            corridors,
+            rotation_mapping,
+            feed_points: scenario.feed_points.to_vec(),
+            rewards: scenario.rewards.clone()
+        })
+    }
 
-    let w: i32 = 12;
-    let h: i32 = 12;
+    /// The warehouse info of the scenario, with its models checked, and the reward model
+    fn info(&mut self, scenario: &Scenario) -> Result<(Info<'_>, &WarehouseRewardModel), Error> {
+        let Site { layout, racks, corridors, rotation_mapping, feed_points, rewards } = self;
+        let mut info = match layout {
+            Some(layout) => { layout.make_info(racks, corridors, rotation_mapping) }
+            None => {
+                let mut info = Info::make(
+                    racks,
+                    corridors,
+                    rotation_mapping,
+                    &feed_points[..],
+                    scenario.width,
+                    scenario.height
+                );
+                info.set_racks(None);
+                info.set_corridors(None);
+                info.set_traffic_rules(None);
+                info.set_rotation_mapping();
+                info
+            }
+        };
+        info.set_grid_square(scenario.grid_square);
+        info.motion_model = scenario.motion_model;
+        info.battery = scenario.battery.clone();
+        // the low fidelity delays used for task allocation follow the high fidelity motion model
+        // unless the scenario gives the slip, congestion is added per block
+        info.low_res_noise = LowResNoise::from_motion_model(&info.motion_model);
+        if let Some(slip) = scenario.low_res_slip {
+            info.low_res_noise.slip = slip;
+        }
+        for (block, c) in scenario.congestion.iter() {
+            info.low_res_noise.set_congestion(*block, *c);
+        }
+        info.validate_model()?;
+        Ok((info, rewards))
+    }
+}
+
+/// The info of a stage after build-scpm, with the agent starts and queue points of the setup
+fn setup_info<'a>(
+    site: &'a mut Site,
+    scenario: &Scenario,
+    setup: &SetupArtifact
+) -> Result<(Info<'a>, &'a WarehouseRewardModel), Error> {
+    let (mut info, rewards) = site.info(scenario)?;
+    info.agent_start_positions = setup.agent_starts.to_vec();
+    info.queue_points = setup.queue_points.to_vec();
+    Ok((info, rewards))
+}
+
+// ---------------------------------------------------------
+//                        build-scpm
+// ---------------------------------------------------------
+fn build_scpm(scenario: &Scenario) -> StageResult {
+    let na: usize = scenario.agents;
+    let nt: usize = scenario.tasks;
+    let mut site = Site::load(scenario)?;
+    let (mut warehouse_info, rewards) = site.info(scenario)?;
+    let (w, h) = (warehouse_info.width, warehouse_info.height);
+    // the layout's queue points and agent starts take precedence over the scenario
+    let queue_points: Vec<Point> = if warehouse_info.queue_points.is_empty() {
+        scenario.queue_points.to_vec()
+    } else {
+        warehouse_info.queue_points.to_vec()
+    };
+    let agent_start_pos: Vec<Point> = if warehouse_info.agent_start_positions.is_empty() {
+        scenario.agent_start_positions(w, h)
+    } else {
+        warehouse_info.agent_start_positions.to_vec()
+    };
+    if queue_points.len() < na || agent_start_pos.len() < na {
+        return Err(format!(
+            "{} agents need as many queue points and start positions, found {} and {}",
+            na, queue_points.len(), agent_start_pos.len()
+        ).into())
+    }
+    // a layout may declare fewer racks or feed points than the scenario needs
+    if warehouse_info.rack_positions.len() < nt {
+        return Err(format!(
+            "{} tasks need as many racks, found {}", nt, warehouse_info.rack_positions.len()
+        ).into())
+    }
+    if warehouse_info.feed_points.is_empty() {
+        return Err("the tasks need at least one feed point, found none".into())
+    }
     // ---------------------------------------------------------
     //                Warehouse Setup information
     // ---------------------------------------------------------
-    let mut rnd = StdRng::seed_from_u64(1234);
-    let feed_points = vec![(0, 5)];
-    let queue_points = vec![(11, 11), (0, 11), (3, 11), (9, 0)];
+    let mut rnd = StdRng::seed_from_u64(scenario.seed);
     // randomly choose the feed points for each task
+    let feed_options: Vec<usize> = (0..warehouse_info.feed_points.len()).collect();
     let task_feed_points = (0..nt)
-        .map(|_| *vec![0].choose(&mut rnd).unwrap()).collect::<Vec<usize>>();
-    let (mut racks, mut corridors, mut rotation_mapping) =
-        warehouse_defaults();
-    let mut warehouse_info: Info = Info::make(
-        &mut racks,
-        &mut corridors,
-        &mut rotation_mapping,
-        &feed_points[..],
-        w as usize,
-        h as usize
-    );
-    warehouse_info.set_racks(None);
-    warehouse_info.set_corridors(None);
-    warehouse_info.set_rotation_mapping();
-
-    let weight_vector: hashbrown::HashMap<i32, Vec<f64>>;
-    let mus: Vec<hashbrown::HashMap<(i32, i32), Vec<f64>>>;
-    let mut allocation: hashbrown::HashMap<(usize, usize), usize> = hashbrown::HashMap::new();
-
+        .map(|_| feed_options.choose(&mut rnd).copied())
+        .collect::<Option<Vec<usize>>>()
+        .ok_or("the tasks need at least one feed point, found none")?;
     let task_positions: Vec<usize> = warehouse_info.rack_positions
         .iter()
         .enumerate()
         .map(|(i, _p)| i)
         .choose_multiple(&mut rnd, nt);
 
-    let mut agent_start_pos: Vec<Point> = vec![];//(2, 0), (3, 0), (4, 11)];
-    for x in 2..w {
-        if agent_start_pos.len() < na {
-            agent_start_pos.push((x, 0));
-            agent_start_pos.push((x, h - 1));
+    warehouse_info.agent_start_positions = agent_start_pos.to_vec();
+    warehouse_info.queue_points = queue_points.to_vec();
+    // check the layout before any products are constructed
+    warehouse_info.validate()?;
+
+    println!("Agent start positions: {:?}", agent_start_pos);
+    println!("Task positions: {:?}\nTask rack points: {:?}",
+             task_positions,
+             task_positions.iter().map(|&i| &warehouse_info.rack_positions[i]).collect::<Vec<&Point>>()
+    );
+    println!("Task feeds: {:?}", task_feed_points);
+    println!("Queue points: {:?}", queue_points);
+    println!("Feed Points: {:?}", warehouse_info.feed_points);
+
+    let mut setup = SetupArtifact {
+        scenario: scenario.clone(),
+        task_racks: task_positions,
+        task_feeds: task_feed_points,
+        agent_starts: agent_start_pos,
+        queue_points,
+        initial_states: Vec::new(),
+        scpm_states: 0,
+        scpm_transitions: 0
+    };
+    let (scpm, _, initial_states) =
+        construct_scpm(&mut warehouse_info, rewards, &setup, true, threads(scenario.threads_load))?;
+    println!("MDP |S|: {:?}, |P|: {:?}", scpm.states, scpm.num_transitions);
+    let mut initial_states: Vec<(i32, i32, usize)> = initial_states.into_iter().map(|((a, t), s)| (a, t, s)).collect();
+    initial_states.sort();
+    setup.initial_states = initial_states;
+    setup.scpm_states = scpm.states;
+    setup.scpm_transitions = scpm.num_transitions;
+    let path = setup.save()?;
+    println!("wrote {}", path.display());
+    Checkpoint::complete("build-scpm", scenario)?;
+    Ok(())
+}
+
+/// Construct the low fidelity products of every agent and task and add them to an SCPM. The
+/// sparse matrices of the products are only written to disk when `write_matrices` is set, later
+/// stages rebuild the SCPM index from the products and read the matrices written by build-scpm.
+///
+/// Returns the SCPM, the number of low fidelity actions and the initial product state of each
+/// (agent, task)
+fn construct_scpm(
+    warehouse_info: &mut Info,
+    rewards: &WarehouseRewardModel,
+    setup: &SetupArtifact,
+    write_matrices: bool,
+    cpus_used_load: usize
+) -> Result<(SCPM, usize, HashMap<(i32, i32), usize>), Error> {
+    let na = setup.scenario.agents;
+    let nt = setup.scenario.tasks;
+    let agent_start_pos = &setup.agent_starts;
+    let Q = (0..5).collect::<Vec<i32>>();
+    let mut low_fidelity_warehouse =
+        build_model::<Robot<LowResState, LowResWord>>(4, Default::default(), rewards, warehouse_info)?;
+
+    println!("warehouse init state: {:?}", low_fidelity_warehouse.get_init_state());
+    println!("warehouse init state idx: {:?}", low_fidelity_warehouse.state_mapping.get(&low_fidelity_warehouse.get_init_state()));
+
+    // ------------------------------------------------------------
+    //                     Initialise SCPM
+    // ------------------------------------------------------------
+    let mut scpm = SCPM::incremental_make(
+        low_fidelity_warehouse.num_actions() as i32,
+        na,
+        nt
+    );
+    // ------------------------------------------------------------
+    // Construct a Threadpool to load transition matrices from disk
+    // ------------------------------------------------------------
+    let pool = threadpool::ThreadPool::new(cpus_used_load);
+    println!("Inputting MDPs");
+    let mut initial_robot_states: Vec<LowResState> = vec![Default::default(); na];
+    let mut initial_states: HashMap<(i32, i32), usize> = HashMap::new();
+
+    // ------------------------------------------------------------
+    //                     Construct Initial States
+    // ------------------------------------------------------------
+    //scpm.get_switch_indices()
+    let bar = ProgressBar::new((na * nt) as u64);
+    bar.set_style(ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40.white/red} {pos:>7}/{len:7} {msg}")
+        .progress_chars("##-"));
+    for t in 0..nt {
+        // generate a bunch of random replenishment tasks
+        warehouse_info.lookup_rack = setup.task_racks[t];
+        warehouse_info.feed_option = setup.task_feeds[t];
+        for a in 0..na {
+            bar.inc(1);
+            bar.set_message("constructing initial states");
+            low_fidelity_warehouse.init_state = warehouse_info.grid_abstraction.to_low_res(&agent_start_pos[a]);
+            initial_robot_states[a] = low_fidelity_warehouse.init_state.clone();
+            let mut task = DFA2::<_, _, &Info>::init(
+                0, &Q, &[3], &[], lr_replenishment, Some(&*warehouse_info)
+            );
+            let mdp = low_fidelity_warehouse.product(&mut task, a as i32, t as i32, Some(&*warehouse_info));
+            serialise_state_mapping(&make_serialised_state_map(&mdp.reverse_state_mapping), a as i32, t as i32);
+            let init_idx = *mdp.state_mapping.get(&mdp.init_state)
+                .ok_or_else(|| Error::state_not_in_mapping(&mdp.init_state))?;
+            initial_states.insert((a as i32, t as i32), init_idx);
         }
     }
 
-    // Begin first memory block
-    {
-        let Q = (0..5).collect::<Vec<i32>>();
-        let mut low_fidelity_warehouse: Robot<LowResState, LowResWord> =
-            Robot::make(4, Default::default());
-        low_fidelity_warehouse.state_space(&w, &h, 1);
-        low_fidelity_warehouse.transition_map(&1.0, &w, &h, &warehouse_info);
-
-        println!("Agent start positions: {:?}", agent_start_pos);
-        println!("warehouse init state: {:?}", low_fidelity_warehouse.get_init_state());
-        println!("warehouse init state idx: {:?}", low_fidelity_warehouse.state_mapping.get(&low_fidelity_warehouse.get_init_state()));
-        println!("Task positions: {:?}\nTask rack points: {:?}",
-                 task_positions,
-                 task_positions.iter().map(|&i| &warehouse_info.rack_positions[i]).collect::<Vec<&Point>>()
-        );
-        println!("Task feeds: {:?}", task_feed_points);
-        println!("Queue points: {:?}", queue_points);
-        println!("Feed Points: {:?}", feed_points);
-
-        // ------------------------------------------------------------
-        //                     Initialise SCPM
-        // ------------------------------------------------------------
-
-        let eps: f64 = 0.0001;
-
-        let mut scpm = SCPM::incremental_make(
-            low_fidelity_warehouse.num_actions() as i32,
-            na,
-            nt
-        );
-        // ------------------------------------------------------------
-        // Construct a Threadpool to load transition matrices from disk
-        // ------------------------------------------------------------
-        let pool = threadpool::ThreadPool::new(cpus_used_load);
-        println!("Inputting MDPs");
-        let mut initial_robot_states: Vec<LowResState> = vec![Default::default(); na];
-        let mut initial_states: HashMap<(i32, i32), usize> = HashMap::new();
-
-        // ------------------------------------------------------------
-        //                     Construct Initial States
-        // ------------------------------------------------------------
-        //scpm.get_switch_indices()
-        let bar = ProgressBar::new((na * nt) as u64);
-        bar.set_style(ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:40.white/red} {pos:>7}/{len:7} {msg}")
-            .progress_chars("##-"));
-        for t in 0..nt {
-            // generate a bunch of random replenishment tasks
-            warehouse_info.lookup_rack = task_positions[t];
-            warehouse_info.feed_option = task_feed_points[t];
-            for a in 0..na {
-                bar.inc(1);
-                bar.set_message("constructing initial states");
-                low_fidelity_warehouse.init_state = agent_start_pos[a];
-                initial_robot_states[a] = low_fidelity_warehouse.init_state.clone();
-                let mut task = DFA2::<_, _, &Info>::init(
-                    0, &Q, &[3], &[], lr_replenishment, Some(&warehouse_info)
-                );
-                let mdp = low_fidelity_warehouse.product(&mut task, a as i32, t as i32, Some(&warehouse_info));
-                serialise_state_mapping(&make_serialised_state_map(&mdp.reverse_state_mapping), a as i32, t as i32);
-                let init_idx = *mdp.state_mapping.get(&mdp.init_state).unwrap();
-                initial_states.insert((a as i32, t as i32), init_idx);
-            }
-        }
+    // ---------------------------------------------------------------
+    //                     Construct MDP DFA Products
+    // ---------------------------------------------------------------
 
-        // ---------------------------------------------------------------
-        //                     Construct MDP DFA Products
-        // ---------------------------------------------------------------
-
-        let bar = ProgressBar::new((na * nt) as u64);
-        bar.set_style(ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:40.white/red} {pos:>7}/{len:7} {msg}")
-            .progress_chars("##-"));
-        // For each task
-        for t in 0..nt {
-            // generate nt random replenishment tasks
-            warehouse_info.lookup_rack = task_positions[t];
-            warehouse_info.feed_option = task_feed_points[t];
-            // For each agent
-            for a in 0..na {
-                bar.inc(1);
-                bar.set_message("constructing SCPM");
-                low_fidelity_warehouse.init_state = agent_start_pos[a];
-                let mut task = DFA2::<_,_,&Info>::init(
-                    0, &Q, &[3], &[], lr_replenishment, Some(&warehouse_info)
-                );
-                let mut mdp = low_fidelity_warehouse.product(&mut task, a as i32, t as i32, Some(&warehouse_info));
-
-                let init_idx = *mdp.state_mapping.get(&mdp.init_state).unwrap();
-                let next_agent_idx = if a < na - 1 {
-                    *initial_states.get(&(a as i32 + 1, t as i32)).unwrap()
-                } else {
-                    init_idx
-                };
-
-                let next_task_idx = if t < nt - 1 {
-                    *initial_states.get(&(0, t as i32 + 1)).unwrap()
-                } else {
-                    init_idx
-                };
-                scpm.add_mdp_to_self(&mut mdp, next_agent_idx as usize, next_task_idx as usize);
+    let bar = ProgressBar::new((na * nt) as u64);
+    bar.set_style(ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40.white/red} {pos:>7}/{len:7} {msg}")
+        .progress_chars("##-"));
+    // For each task
+    for t in 0..nt {
+        warehouse_info.lookup_rack = setup.task_racks[t];
+        warehouse_info.feed_option = setup.task_feeds[t];
+        // For each agent
+        for a in 0..na {
+            bar.inc(1);
+            bar.set_message("constructing SCPM");
+            low_fidelity_warehouse.init_state = warehouse_info.grid_abstraction.to_low_res(&agent_start_pos[a]);
+            let mut task = DFA2::<_,_,&Info>::init(
+                0, &Q, &[3], &[], lr_replenishment, Some(&*warehouse_info)
+            );
+            let mut mdp = low_fidelity_warehouse.product(&mut task, a as i32, t as i32, Some(&*warehouse_info));
+
+            let init_idx = *mdp.state_mapping.get(&mdp.init_state)
+                .ok_or_else(|| Error::state_not_in_mapping(&mdp.init_state))?;
+            let next_agent_idx = if a < na - 1 {
+                initial_states[&(a as i32 + 1, t as i32)]
+            } else {
+                init_idx
+            };
+
+            let next_task_idx = if t < nt - 1 {
+                initial_states[&(0, t as i32 + 1)]
+            } else {
+                init_idx
+            };
+            scpm.add_mdp_to_self(&mut mdp, next_agent_idx as usize, next_task_idx as usize);
+            if write_matrices {
                 let act_start = scpm.actions.start;
                 let act_end = scpm.actions.end;
                 pool.execute(move || {
@@ -185,517 +433,620 @@ fn main() {
                 });
             }
         }
-        pool.join();
-        println!("MDP |S|: {:?}, |P|: {:?}", scpm.states, scpm.num_transitions);
-        println!("init state: {:?}", scpm.get_init_state(0, 0));
-        let mut target = vec![-15.; na];
-        let mut ttask = vec![0.99; nt];
-        target.append(&mut ttask);
-
-        let (mus_, _hullset, tnew) =
+    }
+    pool.join();
+    let nacts = low_fidelity_warehouse.action_space().len();
+    Ok((scpm, nacts, initial_states))
+}
+
+// ---------------------------------------------------------
+//                          solve
+// ---------------------------------------------------------
+fn solve(cli: &Scenario, resume: bool) -> StageResult {
+    let setup = SetupArtifact::load()?;
+    let scenario = stage_scenario(&setup, cli);
+    let na = scenario.agents;
+    let nt = scenario.tasks;
+    let mut site = Site::load(&scenario)?;
+    let (mut warehouse_info, rewards) = setup_info(&mut site, &scenario, &setup)?;
+    let (mut scpm, nacts, _) =
+        construct_scpm(&mut warehouse_info, rewards, &setup, false, threads(scenario.threads_load))?;
+    println!("init state: {:?}", scpm.get_init_state(0, 0));
+    let eps: f64 = scenario.eps;
+    let target = scenario.targets();
+
+    // the multi-objective solve is the longest stage, reuse its result if it completed for this
+    // scenario
+    let solved = resume && Checkpoint::resume(&scenario).map_or(false, |c| c.is_complete("imovi", &scenario));
+    let (mus, tnew) = if solved {
+        println!("imovi is complete in the checkpoint, loading the schedulers");
+        let mus = MusArtifact::load()?;
+        (mus.to_mus(), mus.tnew)
+    } else {
+        let (mus, _hullset, tnew) =
             scpm.imovi_hdd_multi_object_solver(eps, &target[..], 10., 0.1);
+        let path = MusArtifact::from_mus(&mus[..], &tnew[..]).save()?;
+        println!("wrote {}", path.display());
+        Checkpoint::complete("imovi", &scenario)?;
+        (mus, tnew)
+    };
 
-        mus = mus_;
-
-        let nacts = low_fidelity_warehouse.action_space().len();
-
-        let mut costs: hashbrown::HashMap<(i32, i32, i32), f64> = hashbrown::HashMap::new();
-        let mut probs: hashbrown::HashMap<(i32, i32), f64> = hashbrown::HashMap::new();
-        // Compute the task allocation
-        for t in 0..nt {
-            let mut allocated = vec![0; mus.len()];
-            for a in 0..na {
-                let init_state = scpm.get_init_state(a as i32, t as i32);
-                for k in 0..mus.len() {
-                    let sched = mus[k].get(&(a as i32, t as i32)).unwrap();
-                    //println!("T: {} => A: {}?, sched: {}, action: {}", t, a, k, sched[*init_state]);
-                    if sched[*init_state] != 0. && allocated[k] == 0 {
-                        // then load in the matrix and calculate the policy value of the agent
-                        // and the probability of completing the task
-                        let (c, p) = scpm.runner_policy_value(
-                            eps,
-                            nacts,
-                            &sched[..],
-                            a as i32,
-                            t as i32
-                        );
-                        probs.insert((t as i32, k as i32), p);
-                        costs.insert((a as i32, t as i32, k as i32), c);
-                        allocated[k] = 1;
-                        allocation.insert((t, k), a);
-                    }
+    let mut allocations: Vec<TaskAllocation> = Vec::new();
+    let mut costs: hashbrown::HashMap<(i32, i32, i32), f64> = hashbrown::HashMap::new();
+    let mut probs: hashbrown::HashMap<(i32, i32), f64> = hashbrown::HashMap::new();
+    // Compute the task allocation
+    for t in 0..nt {
+        let mut allocated = vec![0; mus.len()];
+        for a in 0..na {
+            let init_state = scpm.get_init_state(a as i32, t as i32);
+            for k in 0..mus.len() {
+                let sched = mus[k].get(&(a as i32, t as i32))
+                    .ok_or_else(|| format!("extreme point {} has no scheduler for agent {} task {}", k, a, t))?;
+                if sched[*init_state] != 0. && allocated[k] == 0 {
+                    // then load in the matrix and calculate the policy value of the agent
+                    // and the probability of completing the task
+                    let (c, p) = scpm.runner_policy_value(
+                        eps,
+                        nacts,
+                        &sched[..],
+                        a as i32,
+                        t as i32
+                    );
+                    probs.insert((t as i32, k as i32), p);
+                    costs.insert((a as i32, t as i32, k as i32), c);
+                    allocated[k] = 1;
+                    allocations.push(TaskAllocation { task: t, k, agent: a });
                 }
             }
         }
+    }
 
-        let solution = scpm.gurobi_task_witness(
-            &costs,
-            &probs,
-            &tnew[..],
-            mus.len(),
-            nt,
-            na)
-            .unwrap();
+    let solution = scpm.gurobi_task_witness(
+        &costs,
+        &probs,
+        &tnew[..],
+        mus.len(),
+        nt,
+        na)?;
+
+    let mut weights: Vec<(i32, Vec<f64>)> = solution.into_iter().collect();
+    weights.sort_by_key(|(t, _)| *t);
+    let mut costs: Vec<(i32, i32, i32, f64)> = costs.into_iter().map(|((a, t, k), c)| (a, t, k, c)).collect();
+    costs.sort_by_key(|(a, t, k, _)| (*a, *t, *k));
+    let mut probs: Vec<(i32, i32, f64)> = probs.into_iter().map(|((t, k), p)| (t, k, p)).collect();
+    probs.sort_by_key(|(t, k, _)| (*t, *k));
+    let path = SolutionArtifact { tnew, allocations, costs, probs, weights }.save()?;
+    println!("wrote {}", path.display());
+    Checkpoint::complete("solve", &scenario)?;
+    Ok(())
+}
 
-        weight_vector = solution;
-        // At this point the SCPM, low fidelity warehouse, and anything else that is in the memory
-        // block has been dropped
+// ---------------------------------------------------------
+//                         allocate
+// ---------------------------------------------------------
+fn allocate(cli: &Scenario) -> StageResult {
+    let setup = SetupArtifact::load()?;
+    let solution = SolutionArtifact::load()?;
+    let mut allocation_rng: StdRng = match cli.allocation_seed {
+        Some(seed) => { StdRng::seed_from_u64(seed) }
+        None => { StdRng::from_entropy() }
+    };
+    let mut allocations: Vec<TaskAllocation> = Vec::new();
+    for t in 0..setup.scenario.tasks {
+        let weight_vector = solution.weights.iter()
+            .find(|(task, _)| *task == t as i32)
+            .map(|(_, w)| w)
+            .ok_or_else(|| format!("the solution has no weights for task {}", t))?;
+        let k = weight_vector
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (i, *x)).collect::<Vec<(usize, f64)>>()
+            .choose_weighted(&mut allocation_rng, |elem| elem.1)?.0;
+        let allocation = solution.allocations.iter()
+            .find(|x| x.task == t && x.k == k)
+            .ok_or_else(|| format!("no agent is allocated task {} by scheduler {}", t, k))?;
+        println!("task: {} => {:?}, k = {}, agent allocated => {}", t, weight_vector, k, allocation.agent);
+        allocations.push(*allocation);
     }
+    let path = AllocationArtifact { allocations }.save()?;
+    println!("wrote {}", path.display());
+    Checkpoint::complete("allocate", &stage_scenario(&setup, cli))?;
+    Ok(())
+}
 
-    // construct a task runner function which loads in the required matrices based on the allocation
-    // function
+// ---------------------------------------------------------
+//                           plan
+// ---------------------------------------------------------
+fn plan(cli: &Scenario, json: bool) -> StageResult {
+    let setup = SetupArtifact::load()?;
+    let allocation = AllocationArtifact::load()?;
+    let scenario = stage_scenario(&setup, cli);
+    let na = scenario.agents;
+    let agent_start_pos = &setup.agent_starts;
+    let mut site = Site::load(&scenario)?;
+    let (mut warehouse_info, rewards) = setup_info(&mut site, &scenario, &setup)?;
 
     println!("Making high fidelity warehouse");
     // construct a high fidelity warehouse
-    let mut high_fidelity_warehouse: Robot<State, WarehouseWord> =
-        Robot::warehouse_make(5, Default::default());
-    high_fidelity_warehouse.warehouse_state_space(&warehouse_info.corridor_positions[..]);
-    high_fidelity_warehouse.warehouse_transition_map(&1., &warehouse_info);
+    let mut high_fidelity_warehouse =
+        build_model::<Robot<State, WarehouseWord>>(5, Default::default(), rewards, &warehouse_info)?;
 
     // construct the mdp of the task to the allocated agent
 
-    let mut pi_mappings: std::collections::HashMap<(i32, i32), std::collections::HashMap<String, std::collections::HashMap<String, Vec<TaskActionPair>>>> =
+    let mut pi_mappings: std::collections::HashMap<(i32, i32), Policy> =
         std::collections::HashMap::new();
     let mut agent_costs: Vec<f64> = vec![0.; na];
+    let mut task_costs: Vec<(usize, usize, f64)> = Vec::new();
     let mut allocations_per_agent: Vec<Vec<usize>> = vec![Vec::new(); na];
 
-    for t in 0..nt {
-        let k = weight_vector.get(&(t as i32))
-            .unwrap()
-            .iter()
-            .enumerate()
-            .map(|(i, x)| (i, *x)).collect::<Vec<(usize, f64)>>()
-            .choose_weighted(&mut thread_rng(), |elem| elem.1).unwrap().0;
-        let agent = allocation.get(&(t, k)).unwrap();
-        warehouse_info.lookup_rack = task_positions[t];
-        warehouse_info.feed_option = task_feed_points[t];
-
-        high_fidelity_warehouse.init_state = State {
-            agent_dir: 1,
-            agent_position: agent_start_pos[*agent].clone(),
-            carrying: 0,
-            pack_available: 0,
-            pack_position: (-1, -1)
-        };
-        println!(
-            "task: {} => {:?}, k = {}, agent allocated => {}",
-            t,
-            weight_vector.get(&(t as i32)),
-            k,
-            agent
-        );
+    for TaskAllocation { task: t, agent, .. } in allocation.allocations.iter() {
+        let t = *t;
+        warehouse_info.lookup_rack = setup.task_racks[t];
+        warehouse_info.feed_option = setup.task_feeds[t];
+
+        high_fidelity_warehouse.init_state = warehouse_info.initial_state(agent_start_pos[*agent], 1);
         allocations_per_agent[*agent].push(t);
 
-        let Q = (0..8).collect::<Vec<i32>>();
-        // construct the DFA
-        let mut task = DFA2::<_,_,&Info>::init(
-            0, &Q, &[5], &[7],
-            hr_replenishment, Some(&warehouse_info)
-        );
-        // construct the Product MDP
         println!("making product {} x {}", agent, t);
-        let mut mdp = high_fidelity_warehouse.product(
-            &mut task,
-            *agent as i32,
-            t as i32,
-            Some(&warehouse_info)
-        );
-
-        // Get the initial state of the product DFA
-        let init_idx = *mdp.state_mapping.get(&mdp.init_state).unwrap();
-
-        //println!("Executing planning for product");
-        // Construct all of the necessary data structures to conduct sparse value iteration on the MDP
-        //
-        let eps: f64 = 1e-5;
-        // determine the set of proper policies and randomly choose an initial one
-        let ns: usize = mdp.states.len();
-
-        let proper_policies = proper_policies(&mut mdp);
-        // determine the available actions for each state
-        let available_actions = set_available_actions(
-            &mut mdp,
-            high_fidelity_warehouse.action_space().start,
-            high_fidelity_warehouse.action_space().end
-        );
-        mdp_rewards_fn(
-            &mut mdp,
-            high_fidelity_warehouse.action_space().start,
-            high_fidelity_warehouse.action_space().end
-        );
-
-        let mut cs_matricies: Vec<_> = Vec::new();
-        let mut rewards_map: HashMap<i32, Vec<f64>> = HashMap::new();
-
-        let mdp_reverse_state_mapping: HashMap<usize, (i32, i32)>;
-
-        {
-            // compute the sparse matricies involved; Transitions and Rewards for each action
-            // the mdp get consumed at this step and therefore we need to save the reverse state map to
-            // memory before we consume the MDP
-            mdp_reverse_state_mapping = mdp.reverse_state_mapping.clone();
-
-            let (transition_matrices, mut rewards_matrices) = construct_spblas_and_rewards(
-                mdp,
-                high_fidelity_warehouse.action_space().start,
-                high_fidelity_warehouse.action_space().end,
-            );
-            for action in high_fidelity_warehouse.action_space().start..high_fidelity_warehouse.action_space().end {
-                let S = transition_matrices.get(&action).unwrap();
-                cs_matricies.push(SparseMatrixAttr {
-                    m: sparse_to_cs(S),
-                    nr: S.nr as usize,
-                    nc: S.nc as usize,
-                    nnz: S.nz as usize
-                });
-                rewards_map.insert(action, rewards_matrices.remove(&action).unwrap());
-            }
-        }
+        let (pi, cost) = synthesise_scheduler(
+            &mut high_fidelity_warehouse,
+            &TaskDfa::replenishment(),
+            hr_replenishment,
+            (*agent as i32, t as i32),
+            &warehouse_info,
+            scenario.hr_eps
+        )?;
+        pi_mappings.insert((*agent as i32, t as i32), pi);
+        agent_costs[*agent] += cost;
+        task_costs.push((*agent, t, cost));
+    }
+    write_schedulers(
+        pi_mappings.into_iter()
+            .map(|((a, t), v)| (task_scheduler_path(a, t), v))
+            .collect(),
+        json,
+        threads(scenario.threads_save)
+    )?;
+    let path = PlanArtifact { agent_costs, task_costs, allocations_per_agent }.save()?;
+    println!("wrote {}", path.display());
+    Checkpoint::complete("plan", &scenario)?;
+    Ok(())
+}
 
-        let (pi, objvals) = mdp_sparse_value_iter(
-            eps,
-            high_fidelity_warehouse.action_space().len(),
-            high_fidelity_warehouse.actions.start,
-            high_fidelity_warehouse.actions.end,
-            ns,
-            init_idx,
-            &proper_policies,
-            &available_actions,
-            &cs_matricies[..],
-            &rewards_map
-        );
-        // Construct a file of the scheduler
-        //println!("obj vals: {:?}", objvals);
-
-        pi_mappings.insert((*agent as i32, t as i32), create_decoded_sched_to_file(
-            &pi[..],
-            &mdp_reverse_state_mapping,
-            &high_fidelity_warehouse.reverse_state_mapping
-        ));
-        agent_costs[*agent] += objvals[0];
+/// Build the product of `warehouse` and the task DFA `dfa` with the guards `next` for
+/// `(agent, task)`, solve it by sparse value iteration to `eps` from the initial state of `warehouse`,
+/// and extract the scheduler. Returns the scheduler with its expected cost.
+fn synthesise_scheduler<F>(
+    warehouse: &mut Robot<State, WarehouseWord>,
+    dfa: &TaskDfa,
+    next: F,
+    (agent, task): (i32, i32),
+    info: &Info,
+    eps: f64
+) -> Result<(Policy, f64), Error> where F: Fn(&Data<WarehouseWord, &Info>) -> i32 {
+    // construct the DFA
+    let mut dfa_task = DFA2::<_,_,&Info>::init(
+        dfa.init, &dfa.states(), dfa.accepting, dfa.rejecting,
+        next, Some(info)
+    );
+    // construct the Product MDP
+    let mut mdp = warehouse.product(&mut dfa_task, agent, task, Some(info));
+
+    // Get the initial state of the product DFA
+    let init_idx = *mdp.state_mapping.get(&mdp.init_state)
+        .ok_or_else(|| Error::state_not_in_mapping(&mdp.init_state))?;
+
+    // Construct all of the necessary data structures to conduct sparse value iteration on the MDP
+    // determine the set of proper policies and randomly choose an initial one
+    let ns: usize = mdp.states.len();
+    let (act_start, act_end) = (warehouse.action_space().start, warehouse.action_space().end);
+
+    let proper_policies = proper_policies(&mut mdp);
+    // determine the available actions for each state
+    let available_actions = set_available_actions(&mut mdp, act_start, act_end);
+    mdp_rewards_fn(&mut mdp, act_start, act_end);
+
+    let mut cs_matricies: Vec<_> = Vec::new();
+    let mut rewards_map: HashMap<i32, Vec<f64>> = HashMap::new();
+
+    // compute the sparse matricies involved; Transitions and Rewards for each action
+    // the mdp get consumed at this step and therefore we need to save the reverse state map to
+    // memory before we consume the MDP
+    let mdp_reverse_state_mapping: HashMap<usize, (i32, i32)> = mdp.reverse_state_mapping.clone();
+
+    let (transition_matrices, mut rewards_matrices) = construct_spblas_and_rewards(mdp, act_start, act_end);
+    for action in act_start..act_end {
+        let S = transition_matrices.get(&action)
+            .ok_or_else(|| Error::InvalidModel(format!("the product has no transitions for action {}", action)))?;
+        cs_matricies.push(SparseMatrixAttr {
+            m: sparse_to_cs(S),
+            nr: S.nr as usize,
+            nc: S.nc as usize,
+            nnz: S.nz as usize
+        });
+        let r = rewards_matrices.remove(&action)
+            .ok_or_else(|| Error::InvalidModel(format!("the product has no rewards for action {}", action)))?;
+        rewards_map.insert(action, r);
     }
-    let pool = threadpool::ThreadPool::new(cpus_used_save);
 
-    for ((a, t), v) in pi_mappings.into_iter() {
+    let (pi, objvals) = mdp_sparse_value_iter(
+        eps,
+        warehouse.action_space().len(),
+        warehouse.actions.start,
+        warehouse.actions.end,
+        ns,
+        init_idx,
+        &proper_policies,
+        &available_actions,
+        &cs_matricies[..],
+        &rewards_map
+    );
+    // Construct the scheduler
+    let policy = Policy::from_product(
+        PolicyHeader::for_task(info, dfa),
+        &pi[..],
+        &mdp_reverse_state_mapping,
+        &warehouse.reverse_state_mapping
+    )?;
+    Ok((policy, objvals[0]))
+}
+
+/// Write schedulers to disk in parallel, and with `json` also as JSON for debugging
+fn write_schedulers(
+    schedulers: Vec<(Result<std::path::PathBuf, rusty_robots::artifacts::ArtifactError>, Policy)>,
+    json: bool,
+    cpus_used_save: usize
+) -> StageResult {
+    let pool = threadpool::ThreadPool::new(cpus_used_save);
+    let (tx, rx) = std::sync::mpsc::channel();
+    let n = schedulers.len();
+    for (path, v) in schedulers.into_iter() {
+        let path = path?;
+        let tx = tx.clone();
         pool.execute(move || {
-            let pth = format!("{}/schedulers/", std::env::var("SCPM_HOME").unwrap());
-            let filename = format!("{}/map_{}_{}.txt", pth, a, t);
-            let file = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(filename).unwrap();
-            let f = BufWriter::new(file);
-            serde_json::to_writer_pretty(
-                f,
-                &v
-            ).unwrap();
+            let result = v.to_file(&path)
+                .and_then(|_| if json { v.to_json_file(path.with_extension("json")) } else { Ok(()) });
+            tx.send(result.map_err(|e| e.to_string())).unwrap();
         });
     }
     pool.join();
+    for result in rx.iter().take(n) {
+        result?;
+    }
+    Ok(())
+}
 
+// ---------------------------------------------------------
+//                           regen
+// ---------------------------------------------------------
+fn regen(cli: &Scenario, json: bool) -> StageResult {
+    let setup = SetupArtifact::load()?;
+    let scenario = stage_scenario(&setup, cli);
+    let na = scenario.agents;
+    let agent_start_pos = &setup.agent_starts;
+    let queue_points = &setup.queue_points;
+    let mut site = Site::load(&scenario)?;
+    let (mut warehouse_info, rewards) = setup_info(&mut site, &scenario, &setup)?;
+
+    let mut high_fidelity_warehouse =
+        build_model::<Robot<State, WarehouseWord>>(5, Default::default(), rewards, &warehouse_info)?;
 
     // ------------------------------------------------------
     //                Regeneration Schedulers
     // ------------------------------------------------------
     // Now we need a scheduler which returns the agents back to the queue position
-    let mut regeneration_schedulers: std::collections::HashMap<usize, std::collections::HashMap<String, std::collections::HashMap<String, Vec<TaskActionPair>>>> =
+    let mut regeneration_schedulers: std::collections::HashMap<usize, Policy> =
         std::collections::HashMap::new();
     // For each agent compute a scheduler which gets to the queue position for this agent
     for a in 0..na {
-        high_fidelity_warehouse.init_state = State {
-            agent_dir: 1,
-            agent_position: agent_start_pos[a].clone(),
-            carrying: 0,
-            pack_available: 0,
-            pack_position: (-1, -1)
-        };
+        high_fidelity_warehouse.init_state = warehouse_info.initial_state(agent_start_pos[a], 1);
         warehouse_info.queue_point = queue_points[a].clone();
-        let Q = (0..3).collect::<Vec<i32>>();
-        // construct the DFA
-        let mut task = DFA2::<_,_,&Info>::init(
-            0, &Q, &[1], &[],
-            regeneration, Some(&warehouse_info)
-        );
-        // construct the Product MDP
         println!("making regeneration task");
-        let mut mdp = high_fidelity_warehouse.product(
-            &mut task,
-            a as i32,
-            0,
-            Some(&warehouse_info)
-        );
-
-        // Get the initial state of the product DFA
-        let init_idx = *mdp.state_mapping.get(&mdp.init_state).unwrap();
-
-        //println!("Executing planning for product");
-        // Construct all of the necessary data structures to conduct sparse value iteration on the MDP
-        //
-        let eps: f64 = 1e-5;
-        // determine the set of proper policies and randomly choose an initial one
-        let ns: usize = mdp.states.len();
-
-        let proper_policies = proper_policies(&mut mdp);
-        // determine the available actions for each state
-        let available_actions = set_available_actions(
-            &mut mdp,
-            high_fidelity_warehouse.action_space().start,
-            high_fidelity_warehouse.action_space().end
-        );
-        mdp_rewards_fn(
-            &mut mdp,
-            high_fidelity_warehouse.action_space().start,
-            high_fidelity_warehouse.action_space().end
-        );
-
-        let mut cs_matricies: Vec<_> = Vec::new();
-        let mut rewards_map: HashMap<i32, Vec<f64>> = HashMap::new();
-
-        let mdp_reverse_state_mapping: HashMap<usize, (i32, i32)>;
-
-        {
-            // compute the sparse matricies involved; Transitions and Rewards for each action
-            // the mdp get consumed at this step and therefore we need to save the reverse state map to
-            // memory before we consume the MDP
-            mdp_reverse_state_mapping = mdp.reverse_state_mapping.clone();
-
-            let (transition_matrices, mut rewards_matrices) = construct_spblas_and_rewards(
-                mdp,
-                high_fidelity_warehouse.action_space().start,
-                high_fidelity_warehouse.action_space().end,
-            );
-            for action in high_fidelity_warehouse.action_space().start..high_fidelity_warehouse.action_space().end {
-                let S = transition_matrices.get(&action).unwrap();
-                cs_matricies.push(SparseMatrixAttr {
-                    m: sparse_to_cs(S),
-                    nr: S.nr as usize,
-                    nc: S.nc as usize,
-                    nnz: S.nz as usize
-                });
-                rewards_map.insert(action, rewards_matrices.remove(&action).unwrap());
-            }
-        }
-
-        let (pi, _objvals) = mdp_sparse_value_iter(
-            eps,
-            high_fidelity_warehouse.action_space().len(),
-            high_fidelity_warehouse.actions.start,
-            high_fidelity_warehouse.actions.end,
-            ns,
-            init_idx,
-            &proper_policies,
-            &available_actions,
-            &cs_matricies[..],
-            &rewards_map
-        );
-
-        regeneration_schedulers.insert(a, create_decoded_sched_to_file(
-            &pi[..],
-            &mdp_reverse_state_mapping,
-            &high_fidelity_warehouse.reverse_state_mapping
-        ));
-    }
-    let pool = threadpool::ThreadPool::new(30);
-
-    for (a, v) in regeneration_schedulers.into_iter() {
-        pool.execute(move || {
-            let pth = format!("{}/schedulers/", std::env::var("SCPM_HOME").unwrap());
-            let filename = format!("{}/regen_{}.txt", pth, a);
-            let file = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(filename).unwrap();
-            let f = BufWriter::new(file);
-            serde_json::to_writer_pretty(
-                f,
-                &v
-            ).unwrap();
-        });
+        let (pi, _cost) = synthesise_scheduler(
+            &mut high_fidelity_warehouse,
+            &TaskDfa::regeneration(),
+            regeneration,
+            (a as i32, 0),
+            &warehouse_info,
+            scenario.hr_eps
+        )?;
+        regeneration_schedulers.insert(a, pi);
     }
-    pool.join();
+    write_schedulers(
+        regeneration_schedulers.into_iter()
+            .map(|(a, v)| (regen_scheduler_path(a), v))
+            .collect(),
+        json,
+        threads(scenario.threads_save)
+    )?;
+    println!("wrote {} regeneration schedulers", na);
+    Checkpoint::complete("regen", &scenario)?;
+    Ok(())
 }
 
-fn goto_rack_position(data: &Data<LowResWord, &Info>, qprime: i32, q: i32) -> i32 {
-    let info_ref = data.info.as_ref().unwrap();
-    if data.w.agent_position == info_ref.rack_positions[info_ref.lookup_rack] {
-        return qprime
-    } else {
-        return q
-    }
-}
-
-fn goto_feed_position(data: &Data<LowResWord, &Info>) -> i32 {
-    let info_ref = data.info.as_ref().unwrap();
-    if data.w.agent_position == info_ref.feed_points[info_ref.feed_option] {
-        return 2
-    } else {
-        return 1
-    }
-}
-
-fn finish(q: i32) -> i32 {
-    q
-}
-
-fn done(q: i32) -> i32 {
-    q
+// ---------------------------------------------------------
+//                         simulate
+// ---------------------------------------------------------
+/// Load a scheduler and check that it was synthesised for the layout of `info`
+fn load_policy(path: std::path::PathBuf, info: &Info) -> Result<Policy, Error> {
+    let policy = Policy::from_file(path)?;
+    policy.header.check_layout(info)?;
+    Ok(policy)
 }
 
-fn lr_replenishment(data: &Data<LowResWord, &Info>) -> i32 {
-    let qprime = match data.q {
-        0 => {Ok(goto_rack_position(&data, 0, 1))}
-        1 => {Ok(goto_feed_position(&data))}
-        2 => {Ok(goto_rack_position(&data, 3, 2))}
-        3 => {Ok(finish(4))}
-        4 => {Ok(done(4))}
-        _ => {Err("Q state not found")}
-    };
-    match qprime {
-        Ok(i) => { i }
-        Err(_) => { -1 }
+fn simulate(cli: &Scenario) -> StageResult {
+    let setup = SetupArtifact::load()?;
+    let allocation = AllocationArtifact::load()?;
+    let scenario = stage_scenario(&setup, cli);
+    let mut site = Site::load(&scenario)?;
+    let (mut warehouse_info, rewards) = setup_info(&mut site, &scenario, &setup)?;
+    let warehouse: Robot<State, WarehouseWord> = Robot::make(5, Default::default());
+    let mut rng: StdRng = StdRng::seed_from_u64(scenario.allocation_seed.unwrap_or(scenario.seed));
+    // every agent executes its allocated tasks in order and then returns to its queue point
+    let mut agents: Vec<SimAgent> = Vec::with_capacity(scenario.agents);
+    for agent in 0..scenario.agents {
+        let init = warehouse_info.initial_state(setup.agent_starts[agent], 1);
+        let mut jobs: Vec<Job> = Vec::new();
+        for TaskAllocation { task, .. } in allocation.allocations.iter().filter(|x| x.agent == agent) {
+            jobs.push(Job {
+                task: Some(*task),
+                executor: Executor::new(
+                    load_policy(task_scheduler_path(agent as i32, *task as i32)?, &warehouse_info)?,
+                    TaskDfa::replenishment(),
+                    init
+                ),
+                lookup_rack: setup.task_racks[*task],
+                feed_option: setup.task_feeds[*task],
+                queue_point: setup.queue_points[agent]
+            });
+        }
+        let path = regen_scheduler_path(agent)?;
+        if path.exists() {
+            jobs.push(Job {
+                task: None,
+                executor: Executor::new(load_policy(path, &warehouse_info)?, TaskDfa::regeneration(), init),
+                lookup_rack: 0,
+                feed_option: 0,
+                queue_point: setup.queue_points[agent]
+            });
+        }
+        agents.push(SimAgent::new(agent, init, jobs));
     }
-}
-
-/// DFA transition telling the robot to goto some random rack position
-fn goto_rand_rackpos(data: &Data<WarehouseWord, &Info>) -> i32 {
-    let info_ref = data.info.as_ref().unwrap();
-    let front = front_pos(
-        &data.w.agent_position,
-        &data.w.dir,
-        info_ref.rotation_mapping,
-        info_ref.width,
-        info_ref.height
-    );
-    if *data.w.is_carrying() == 0 {
-        match front {
-            Some(point) => {
-                if point == info_ref.rack_positions[info_ref.lookup_rack] {
-                    return 1
-                }
+    let mut simulator = MultiAgentSimulator::new(agents, MAX_SIMULATION_STEPS, DEADLOCK_STEPS);
+    let report = simulator.run(&warehouse, &mut warehouse_info, rewards, &mut rng)?;
+    for outcome in report.outcomes.iter() {
+        match outcome.task {
+            Some(task) => {
+                println!("agent {} task {}: {:?} in steps {}..{}", outcome.agent, task, outcome.status, outcome.start, outcome.end);
+            }
+            None => {
+                println!("agent {} regeneration: {:?} in steps {}..{}", outcome.agent, outcome.status, outcome.start, outcome.end);
             }
-            None => { }
         }
-    } else {
-        return 7
     }
-    return 0
+    println!("makespan: {}, agent costs: {:?}", report.makespan, report.agent_costs);
+    println!("tasks completed: {}, failed: {}, unfinished: {}, without an action: {}, collisions: {}",
+             report.tasks_completed(), report.tasks_failed(), report.tasks_unfinished(),
+             report.tasks_without_action(), report.collisions.len());
+    if let Some(step) = report.deadlock {
+        println!("deadlocked at step {}", step);
+    }
+    let path = SimulationArtifact { report }.save()?;
+    println!("wrote {}", path.display());
+    Checkpoint::complete("simulate", &scenario)?;
+    Ok(())
 }
 
-fn pickup_rack_at_pos(data: &Data<WarehouseWord, &Info>) -> i32 {
-    if *data.w.is_carrying() == 1 {
-        return 2
-    } else {
-        return 1
+// ---------------------------------------------------------
+//                         evaluate
+// ---------------------------------------------------------
+fn evaluate(cli: &Scenario) -> StageResult {
+    let setup = SetupArtifact::load()?;
+    let solution = SolutionArtifact::load()?;
+    let allocation = AllocationArtifact::load()?;
+    let plan = PlanArtifact::load()?;
+    let scenario = stage_scenario(&setup, cli);
+    let mut site = Site::load(&scenario)?;
+    let (mut warehouse_info, rewards) = setup_info(&mut site, &scenario, &setup)?;
+    let warehouse: Robot<State, WarehouseWord> = Robot::make(5, Default::default());
+    let mut rng: StdRng = StdRng::seed_from_u64(scenario.allocation_seed.unwrap_or(scenario.seed));
+    let monte_carlo = MonteCarlo::new(scenario.episodes, MAX_SIMULATION_STEPS);
+    let mut evaluations = Vec::with_capacity(allocation.allocations.len());
+    for TaskAllocation { task, agent, k } in allocation.allocations.iter() {
+        warehouse_info.lookup_rack = setup.task_racks[*task];
+        warehouse_info.feed_option = setup.task_feeds[*task];
+        let init = warehouse_info.initial_state(setup.agent_starts[*agent], 1);
+        let policy = load_policy(task_scheduler_path(*agent as i32, *task as i32)?, &warehouse_info)?;
+        let mut executor = Executor::new(policy, TaskDfa::replenishment(), init);
+        let mut evaluation = monte_carlo.evaluate(
+            (*agent, *task), &warehouse, &mut executor, &warehouse_info, rewards, &mut rng
+        )?;
+        // the solvers maximise the negated cost
+        evaluation.predicted_cost = plan.task_costs.iter()
+            .find(|(a, t, _)| a == agent && t == task)
+            .map(|(_, _, c)| -c);
+        evaluation.predicted_scpm_cost = solution.costs.iter()
+            .find(|(a, t, j, _)| *a as usize == *agent && *t as usize == *task && *j as usize == *k)
+            .map(|(_, _, _, c)| -c);
+        evaluation.predicted_scpm_prob = solution.probs.iter()
+            .find(|(t, j, _)| *t as usize == *task && *j as usize == *k)
+            .map(|(_, _, p)| *p);
+        println!("agent {} task {}: cost {:.3} [{:.3}, {:.3}] predicted {:?} (SCPM {:?}), \
+                  success {:.3} [{:.3}, {:.3}] predicted {:?}, {} truncated",
+                 agent, task, evaluation.cost.mean, evaluation.cost.lower, evaluation.cost.upper,
+                 evaluation.predicted_cost, evaluation.predicted_scpm_cost,
+                 evaluation.success.mean, evaluation.success.lower, evaluation.success.upper,
+                 evaluation.predicted_scpm_prob, evaluation.truncated);
+        if evaluation.cost_consistent() == Some(false) || evaluation.prob_consistent() == Some(false) {
+            println!("  the prediction is outside the {} episode confidence interval", scenario.episodes);
+        }
+        evaluations.push(evaluation);
     }
+    let path = EvaluationArtifact { episodes: scenario.episodes, evaluations }.save()?;
+    println!("wrote {}", path.display());
+    Checkpoint::complete("evaluate", &scenario)?;
+    Ok(())
 }
 
-fn carry_rack_to_feed0(data: &Data<WarehouseWord, &Info>) -> i32 {
-    let info_ref = data.info.as_ref().unwrap();
-    let front = front_pos(
-        &data.w.agent_position,
-        &data.w.dir,
-        info_ref.rotation_mapping,
-        info_ref.width,
-        info_ref.height
-    );
-    if *data.w.is_carrying() == 1 {
-        match front {
-            Some(point) => {
-                if point == info_ref.feed_points[info_ref.feed_option] {
-                    return 3
-                }
-            }
-            None => { }
+// ---------------------------------------------------------
+//                          export
+// ---------------------------------------------------------
+fn export(cli: &Scenario) -> StageResult {
+    let setup = SetupArtifact::load()?;
+    let allocation = AllocationArtifact::load()?;
+    let scenario = stage_scenario(&setup, cli);
+    let mut site = Site::load(&scenario)?;
+    let (warehouse_info, _) = setup_info(&mut site, &scenario, &setup)?;
+    let allocations: Vec<ExportAllocation> = allocation.allocations.iter()
+        .map(|x| ExportAllocation {
+            task: x.task,
+            agent: x.agent,
+            rack: warehouse_info.rack_positions[setup.task_racks[x.task]],
+            feed: warehouse_info.feed_points[setup.task_feeds[x.task]]
+        })
+        .collect();
+    let path = artifact_dir()?.join("policies.jsonl");
+    let file = std::fs::File::create(&path)?;
+    let mut export = PolicyExport::new(
+        std::io::BufWriter::new(file),
+        ExportLayout::from_info(&warehouse_info),
+        allocations
+    )?;
+    for TaskAllocation { task, agent, .. } in allocation.allocations.iter() {
+        let policy = load_policy(task_scheduler_path(*agent as i32, *task as i32)?, &warehouse_info)?;
+        export.write_policy(*agent, Some(*task), &TaskDfa::replenishment(), &policy)?;
+    }
+    for agent in 0..scenario.agents {
+        let regen_path = regen_scheduler_path(agent)?;
+        if regen_path.exists() {
+            export.write_policy(agent, None, &TaskDfa::regeneration(), &load_policy(regen_path, &warehouse_info)?)?;
         }
-    } else {
-        return 7
     }
-    return 2
+    export.finish()?;
+    println!("wrote {}", path.display());
+    Ok(())
 }
 
-// return the rack back to its original position
-fn carry_rack_back_to_pos(data: &Data<WarehouseWord, &Info>) -> i32 {
-    let info_ref = data.info.as_ref().unwrap();
-    let front = front_pos(
-        &data.w.agent_position,
-        &data.w.dir,
-        info_ref.rotation_mapping,
-        info_ref.width,
-        info_ref.height
-    );
-    if *data.w.is_carrying() == 1 {
-        match front {
-            Some(point) => {
-                if point == info_ref.rack_positions[info_ref.lookup_rack] {
-                    return 4
-                }
+// ---------------------------------------------------------
+//                          inspect
+// ---------------------------------------------------------
+fn inspect() -> StageResult {
+    match Checkpoint::load() {
+        Ok(checkpoint) => {
+            println!("checkpoint: version {}, scenario {:016x}, completed {:?}",
+                     checkpoint.version, checkpoint.scenario_hash, checkpoint.completed);
+        }
+        Err(e) => { println!("checkpoint: {}", e); }
+    }
+    match SetupArtifact::load() {
+        Ok(setup) => {
+            println!("setup: {} agents, {} tasks, layout {:?}, seed {}",
+                     setup.scenario.agents, setup.scenario.tasks, setup.scenario.layout, setup.scenario.seed);
+            println!("  task racks: {:?}, task feeds: {:?}", setup.task_racks, setup.task_feeds);
+            println!("  SCPM |S|: {}, |P|: {}", setup.scpm_states, setup.scpm_transitions);
+            let schedulers = (0..setup.scenario.agents)
+                .flat_map(|a| (0..setup.scenario.tasks).map(move |t| (a, t)))
+                .filter(|(a, t)| task_scheduler_path(*a as i32, *t as i32).map(|p| p.exists()).unwrap_or(false))
+                .count();
+            let regen = (0..setup.scenario.agents)
+                .filter(|a| regen_scheduler_path(*a).map(|p| p.exists()).unwrap_or(false))
+                .count();
+            println!("  task schedulers on disk: {}, regeneration schedulers on disk: {}", schedulers, regen);
+        }
+        Err(e) => { println!("setup: {}", e); }
+    }
+    match MusArtifact::load() {
+        Ok(mus) => { println!("mus: {} extreme points", mus.mus.len()); }
+        Err(e) => { println!("mus: {}", e); }
+    }
+    match SolutionArtifact::load() {
+        Ok(solution) => { println!("solution: tnew = {:?}", solution.tnew); }
+        Err(e) => { println!("solution: {}", e); }
+    }
+    match AllocationArtifact::load() {
+        Ok(allocation) => {
+            for x in allocation.allocations.iter() {
+                println!("allocation: task {} => agent {} (k = {})", x.task, x.agent, x.k);
             }
-            None => { }
         }
-    } else {
-        return 7
+        Err(e) => { println!("allocation: {}", e); }
     }
-    return 3
-}
-
-fn drop_rack(data: &Data<WarehouseWord, &Info>) -> i32 {
-    if *data.w.is_carrying() == 0 {
-        return 5
-    } else {
-        return 4
+    match PlanArtifact::load() {
+        Ok(plan) => {
+            println!("plan: agent costs {:?}, tasks per agent {:?}", plan.agent_costs, plan.allocations_per_agent);
+        }
+        Err(e) => { println!("plan: {}", e); }
     }
-}
-
-fn fail() -> i32 {
-    7
-}
-
-fn hr_replenishment(data: &Data<WarehouseWord, &Info>) -> i32 {
-    //let qfail = 6;
-    let qprime = match data.q {
-        0 => {Ok(goto_rand_rackpos(&data))}
-        1 => {Ok(pickup_rack_at_pos(&data))}
-        2 => {Ok(carry_rack_to_feed0(&data))}
-        3 => {Ok(carry_rack_back_to_pos(&data))}
-        4 => {Ok(drop_rack(&data))}
-        5 => {Ok(finish(6))}
-        6 => {Ok(done(6))}
-        7 => {Ok(fail())}
-        _ => {Err("Q state not found")}
-    };
-    match qprime {
-        Ok(i) =>  { i }
-        Err(_) => { -1 }
+    match SimulationArtifact::load() {
+        Ok(simulation) => {
+            let report = &simulation.report;
+            println!("simulation: makespan {}, {} tasks completed, {} failed, {} unfinished, {} without an action, \
+                      {} collisions, deadlock {:?}",
+                     report.makespan, report.tasks_completed(), report.tasks_failed(), report.tasks_unfinished(),
+                     report.tasks_without_action(), report.collisions.len(), report.deadlock);
+        }
+        Err(e) => { println!("simulation: {}", e); }
     }
-}
-
-fn goto_queue_pos(data: &Data<WarehouseWord, &Info>) -> i32 {
-    let info_ref: &&Info = data.info.as_ref().unwrap();
-    if data.w.agent_position == info_ref.queue_point {
-        return 1
+    match EvaluationArtifact::load() {
+        Ok(evaluation) => {
+            for e in evaluation.evaluations.iter() {
+                println!("evaluation: agent {} task {}: cost {:.3} (predicted {:?}), success {:.3} (predicted {:?})",
+                         e.agent, e.task, e.cost.mean, e.predicted_cost, e.success.mean, e.predicted_scpm_prob);
+            }
+        }
+        Err(e) => { println!("evaluation: {}", e); }
     }
-    return 0
+    Ok(())
 }
 
-fn finish_regen() -> i32 {
-    2
-}
-
-fn done_regen() -> i32 {
-    2
-}
-
-fn regeneration(data: &Data<WarehouseWord, &Info>) -> i32 {
-    let qprime = match data.q {
-        0 => { Ok(goto_queue_pos(&data)) }
-        1 => {Ok(finish_regen())}
-        2 => {Ok(done_regen())}
-        _ => { Err("Q state not found")}
+// ---------------------------------------------------------
+//                          render
+// ---------------------------------------------------------
+/// Draw the layout of the setup, or of the command line scenario before build-scpm, then overlay
+/// whatever the later stages have written
+fn render(cli: &Scenario) -> StageResult {
+    let setup = SetupArtifact::load().ok();
+    let scenario = setup.as_ref().map_or_else(|| cli.clone(), |setup| stage_scenario(setup, cli));
+    let mut site = Site::load(&scenario)?;
+    let (mut warehouse_info, _) = match &setup {
+        Some(setup) => { setup_info(&mut site, &scenario, setup)? }
+        None => { site.info(&scenario)? }
     };
-    match qprime {
-        Ok(i) => { i }
-        Err(_) => { -1 }
+    let dir = artifact_dir()?;
+    let starts: Vec<State> = warehouse_info.agent_start_positions.iter()
+        .map(|p| warehouse_info.initial_state(*p, 1))
+        .collect();
+    let overlay = Overlay::new(starts);
+    println!("layout:\n{}", warehouse_info.render_ascii(&overlay));
+    std::fs::write(dir.join("layout.svg"), warehouse_info.render_svg(&overlay))?;
+    if let (Some(setup), Ok(allocation)) = (&setup, AllocationArtifact::load()) {
+        let warehouse: Robot<State, WarehouseWord> = Robot::make(5, Default::default());
+        for TaskAllocation { task, agent, .. } in allocation.allocations.iter() {
+            let path = task_scheduler_path(*agent as i32, *task as i32)?;
+            if !path.exists() {
+                continue;
+            }
+            warehouse_info.lookup_rack = setup.task_racks[*task];
+            warehouse_info.feed_option = setup.task_feeds[*task];
+            let init = warehouse_info.initial_state(setup.agent_starts[*agent], 1);
+            let mut executor = Executor::new(load_policy(path, &warehouse_info)?, TaskDfa::replenishment(), init);
+            let trajectory = executor.greedy_trajectory(&warehouse, &warehouse_info, MAX_SIMULATION_STEPS)?;
+            let overlay = Overlay::from_trajectories(&[trajectory]);
+            println!("agent {} task {}, greedy trajectory ({:?}):\n{}",
+                     agent, task, executor.status(), warehouse_info.render_ascii(&overlay));
+            std::fs::write(dir.join(format!("trajectory_{}_{}.svg", agent, task)), warehouse_info.render_svg(&overlay))?;
+        }
     }
-}
\ No newline at end of file
+    if let Ok(simulation) = SimulationArtifact::load() {
+        let overlay = Overlay::from_trajectories(&simulation.report.trajectories[..]);
+        println!("simulation, step {}:\n{}", simulation.report.steps, warehouse_info.render_ascii(&overlay));
+        std::fs::write(dir.join("simulation.svg"), warehouse_info.render_svg(&overlay))?;
+        #[cfg(feature = "gif")]
+        {
+            let file = std::fs::File::create(dir.join("simulation.gif"))?;
+            warehouse_info.simulation_gif(&simulation.report, &Default::default(), std::io::BufWriter::new(file))?;
+        }
+    }
+    println!("wrote the SVG renderings to {}", dir.display());
+    Ok(())
+}
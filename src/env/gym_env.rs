@@ -1,12 +1,311 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 use scpm::agent::Agent;
+use crate::env::reward::RewardTable;
+use crate::error::Error;
 
-pub trait Env<T, S, W> where T: Agent<S, W>, W: Clone {
-    fn make(na: i32, init_state: S) -> T;
+/// The (state, probability, word) outcomes of a step
+pub type Outcomes<S, W> = Vec<(S, f64, W)>;
 
-    fn state_space(&mut self);
+/// How far the probabilities of the outcomes of a step may sum from 1
+pub const DISTRIBUTION_TOLERANCE: f64 = 1e-6;
 
-    fn step(&mut self, state: &S, a: i32) -> Result<Vec<(S, f64, W)>, &'static str>;
+/// Sample one of the `outcomes` of a step by its probability. The probabilities must be
+/// non-negative and sum to 1 within `DISTRIBUTION_TOLERANCE`.
+pub fn sample_outcome<'o, S, W, R: Rng + ?Sized>(outcomes: &'o [(S, f64, W)], rng: &mut R)
+    -> Result<&'o (S, f64, W), Error> {
+    let total: f64 = outcomes.iter().map(|o| o.1).sum();
+    if !outcomes.is_empty() && (total - 1.).abs() > DISTRIBUTION_TOLERANCE {
+        return Err(Error::InvalidDistribution(format!("the outcome probabilities sum to {}", total)))
+    }
+    outcomes.choose_weighted(rng, |o| o.1).map_err(|e| Error::InvalidDistribution(e.to_string()))
+}
 
-    fn transition_map(&mut self, r: &f64);
+/// The most likely of the `outcomes` of a step, the first of these on a tie
+pub fn most_likely_outcome<S, W>(outcomes: &[(S, f64, W)]) -> Result<&(S, f64, W), Error> {
+    outcomes.iter()
+        .reduce(|best, o| if o.1 > best.1 { o } else { best })
+        .ok_or_else(|| Error::InvalidDistribution("the step has no outcomes".to_string()))
 }
 
+/// An environment the SCPM is built from. The model of an environment depends on its states,
+/// actions and words, and on a context it is built in, the warehouse `Info` for the warehouses or
+/// `()` for a self-contained environment. The transition map and reward table are built from `step`
+/// and `reward` for every state of the state space and every action.
+pub trait Env: Sized {
+    type State;
+    type Word: Clone;
+    type Context<'c>;
+    /// The reward model consulted for every state-action pair, e.g. `dyn RewardModel<State>`
+    type Rewards: ?Sized;
+
+    fn make(na: i32, init_state: Self::State) -> Self;
+
+    fn state_space(&mut self, ctx: &Self::Context<'_>);
+
+    /// The outcomes of taking `a` in `state`, `Error::ActionNotEnabled` if `a` can not be taken in
+    /// `state` and `Error::UnknownAction` if `a` is not an action of the environment at all
+    fn step(&self, state: &Self::State, a: i32, ctx: &Self::Context<'_>)
+        -> Result<Outcomes<Self::State, Self::Word>, Error>;
+
+    /// The reward of taking `a` in `state`, one entry per reward dimension
+    fn reward(&self, rewards: &Self::Rewards, state: &Self::State, a: i32, ctx: &Self::Context<'_>) -> Vec<f64>;
+
+    /// The single reward of the transition map from the reward dimensions
+    fn scalarise(&self, _rewards: &Self::Rewards, r: &[f64]) -> f64 {
+        r.iter().sum()
+    }
+
+    /// Build the transition map, storing the scalarised reward of each state-action pair. Actions
+    /// which `step` reports as not enabled in a state are left out, any other error, including an
+    /// action space with actions the environment does not know, is returned.
+    fn transition_map(&mut self, rewards: &Self::Rewards, ctx: &Self::Context<'_>) -> Result<(), Error>
+    where Self: Agent<Self::State, Self::Word>, Self::State: Clone + Debug + Hash + Eq {
+        let states = self.get_states().to_vec();
+        for state in states.iter() {
+            let sidx = *self.get_state_mapping().get(state)
+                .ok_or_else(|| Error::state_not_in_mapping(state))? as i32;
+            for a in self.action_space().clone() {
+                let sprimes = match self.step(state, a, ctx) {
+                    Ok(v) => { v }
+                    Err(Error::ActionNotEnabled(_)) => { continue }
+                    Err(e) => { return Err(e) }
+                };
+                let sprime_mapping: Vec<(i32, f64, Self::Word)> = sprimes
+                    .into_iter()
+                    .map(|(s, p, w)|
+                        self.get_state_mapping().get(&s)
+                            .map(|sidx| (*sidx as i32, p, w))
+                            .ok_or_else(|| Error::state_not_in_mapping(&s))
+                    )
+                    .collect::<Result<_, Error>>()?;
+                self.insert_transition(sidx, a, sprime_mapping);
+                let r = self.reward(rewards, state, a, ctx);
+                let r = self.scalarise(rewards, &r[..]);
+                self.insert_reward(sidx, a, r);
+            }
+        }
+        Ok(())
+    }
+
+    /// The full reward vector of each state-action pair
+    fn reward_table(&self, rewards: &Self::Rewards, ctx: &Self::Context<'_>) -> Result<RewardTable, Error>
+    where Self: Agent<Self::State, Self::Word>, Self::State: Debug + Hash + Eq {
+        let mut table: RewardTable = RewardTable::new();
+        for state in self.get_states().iter() {
+            let sidx = *self.get_state_mapping().get(state)
+                .ok_or_else(|| Error::state_not_in_mapping(state))? as i32;
+            for a in self.action_space().clone() {
+                table.insert((sidx, a), self.reward(rewards, state, a, ctx));
+            }
+        }
+        Ok(table)
+    }
+}
+
+/// Make an environment and build its state space and transition map
+pub fn build_model<E>(na: i32, init_state: E::State, rewards: &E::Rewards, ctx: &E::Context<'_>) -> Result<E, Error>
+where E: Env + Agent<E::State, E::Word>, E::State: Clone + Debug + Hash + Eq {
+    let mut env = E::make(na, init_state);
+    env.state_space(ctx);
+    env.transition_map(rewards, ctx)?;
+    Ok(env)
+}
+
+/*
+The episodic interface samples trajectories of an environment one action at a time, for training
+and comparing learned policies against the synthesised schedulers. An episode steps the product of
+the environment and a task DFA: the observation is the robot state with the DFA state, the reward
+of an action is its negated cost, so that returns compare with the negated expected costs the
+solvers maximise, and the episode is done once the DFA has finished the task or rejected it, or
+after the step limit.
+*/
+
+/// The task an episode is done on, a DFA over the words of the environment
+pub trait TaskProgress<W> {
+    fn init(&self) -> i32;
+
+    /// The DFA state after entering a state with word `w` from `q`
+    fn next(&self, q: i32, w: &W) -> i32;
+
+    fn is_accepting(&self, q: i32) -> bool;
+
+    /// Whether the episode ends once the DFA is in `q`
+    fn is_done(&self, q: i32) -> bool;
+}
+
+/// A task DFA over the words of an environment without a warehouse `Info`
+#[derive(Clone)]
+pub struct Dfa<W> {
+    pub init: i32,
+    pub accepting: Vec<i32>,
+    /// The accepting and rejecting states, and any states only reachable through them
+    pub done: Vec<i32>,
+    pub next: fn(i32, &W) -> i32
+}
+
+impl<W> TaskProgress<W> for Dfa<W> {
+    fn init(&self) -> i32 {
+        self.init
+    }
+
+    fn next(&self, q: i32, w: &W) -> i32 {
+        (self.next)(q, w)
+    }
+
+    fn is_accepting(&self, q: i32) -> bool {
+        self.accepting.contains(&q)
+    }
+
+    fn is_done(&self, q: i32) -> bool {
+        self.done.contains(&q)
+    }
+}
+
+/// The robot state and DFA state of an episode, a scheduler's action for it is
+/// `policy.action(&observation.state, observation.q)`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Observation<S> {
+    pub state: S,
+    pub q: i32
+}
+
+/// What happened in a step besides the observation, reward and done flag
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StepInfo {
+    /// The probability of the sampled outcome
+    pub probability: f64,
+    /// The DFA has accepted the task
+    pub accepted: bool,
+    /// The episode was cut off at the step limit
+    pub truncated: bool,
+    /// The actions taken in the episode so far
+    pub steps: usize
+}
+
+/// The observation, reward, done flag and info of a step of an episode
+pub type Transition<S> = (Observation<S>, f64, bool, StepInfo);
+
+/// Samples episodes of `env`, built in `ctx`, on `task`, starting every episode in `init`
+pub struct EpisodicEnv<'e, 'c, E: Env, T> {
+    pub env: &'e E,
+    pub ctx: &'e E::Context<'c>,
+    pub rewards: &'e E::Rewards,
+    pub task: T,
+    pub init: E::State,
+    pub max_steps: usize,
+    state: E::State,
+    q: i32,
+    steps: usize,
+    done: bool,
+    rng: StdRng
+}
+
+impl<'e, 'c, E, T> EpisodicEnv<'e, 'c, E, T> where E: Env, E::State: Copy, T: TaskProgress<E::Word> {
+    /// An environment in the first state of an episode, with outcomes sampled from seed 0 until
+    /// it is reset with another seed
+    pub fn new(
+        env: &'e E,
+        ctx: &'e E::Context<'c>,
+        rewards: &'e E::Rewards,
+        task: T,
+        init: E::State,
+        max_steps: usize
+    ) -> EpisodicEnv<'e, 'c, E, T> {
+        let q = task.init();
+        EpisodicEnv {
+            env,
+            ctx,
+            rewards,
+            task,
+            init,
+            max_steps,
+            state: init,
+            q,
+            steps: 0,
+            done: false,
+            rng: StdRng::seed_from_u64(0)
+        }
+    }
+
+    /// Start a new episode, reseeding the sampler if `seed` is given
+    pub fn reset(&mut self, seed: Option<u64>) -> Observation<E::State> {
+        if let Some(seed) = seed {
+            self.rng = StdRng::seed_from_u64(seed);
+        }
+        self.state = self.init;
+        self.q = self.task.init();
+        self.steps = 0;
+        self.done = self.task.is_done(self.q);
+        self.observation()
+    }
+
+    pub fn observation(&self) -> Observation<E::State> {
+        Observation { state: self.state, q: self.q }
+    }
+
+    /// Take `action`, sampling the next state from the transition distribution. Returns the
+    /// observation, the reward, whether the episode is done and the step info.
+    pub fn step(&mut self, action: i32) -> Result<Transition<E::State>, Error> {
+        if self.done {
+            return Err(Error::EpisodeFinished)
+        }
+        let cost = self.env.reward(self.rewards, &self.state, action, self.ctx);
+        let reward = -self.env.scalarise(self.rewards, &cost[..]);
+        let outcomes = self.env.step(&self.state, action, self.ctx)?;
+        let (next, probability, word) = sample_outcome(&outcomes, &mut self.rng)?;
+        self.state = *next;
+        self.q = self.task.next(self.q, word);
+        self.steps += 1;
+        let accepted = self.task.is_accepting(self.q);
+        let finished = self.task.is_done(self.q);
+        let truncated = !finished && self.steps >= self.max_steps;
+        self.done = finished || truncated;
+        let info = StepInfo { probability: *probability, accepted, truncated, steps: self.steps };
+        Ok((self.observation(), reward, self.done, info))
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use scpm::agent::Robot;
+    use crate::env::reward::ConstantReward;
+    use crate::env::warehouse::high_fidelity_warehouse::{State, warehouse_defaults, WarehouseWord};
+    use crate::env::warehouse::layout::WarehouseLayout;
+    use super::*;
+
+    #[test]
+    fn transition_map_returns_unknown_actions() {
+        let layout = WarehouseLayout::from_ascii("F...\n.RR.\n....").unwrap();
+        let (mut racks, mut corridors, mut rotation_mapping) = warehouse_defaults();
+        let info = layout.make_info(&mut racks, &mut corridors, &mut rotation_mapping);
+        let warehouse = build_model::<Robot<State, WarehouseWord>>(5, Default::default(), &ConstantReward(1.), &info)
+            .unwrap();
+        assert_eq!(warehouse.transitions.len(), warehouse.states.len() * 5);
+        // there are only 5 warehouse actions, the sixth is not left out of the model
+        let warehouse = build_model::<Robot<State, WarehouseWord>>(6, Default::default(), &ConstantReward(1.), &info);
+        assert!(matches!(warehouse, Err(Error::UnknownAction(5))));
+    }
+
+    #[test]
+    fn outcomes_which_are_not_a_distribution_are_reported() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let outcomes: Vec<(i32, f64, ())> = vec![(0, 0.2, ()), (1, 0.4, ()), (2, 0.4, ()), (3, 0., ())];
+        assert_eq!(most_likely_outcome(&outcomes).unwrap().0, 1);
+        assert!(sample_outcome(&outcomes, &mut rng).unwrap().0 < 3);
+        let unnormalised: Vec<(i32, f64, ())> = vec![(0, 0.25, ()), (1, 0.5, ()), (2, 0.5, ())];
+        assert!(matches!(sample_outcome(&unnormalised, &mut rng), Err(Error::InvalidDistribution(_))));
+        let none: Vec<(i32, f64, ())> = Vec::new();
+        assert!(matches!(sample_outcome(&none, &mut rng), Err(Error::InvalidDistribution(_))));
+        assert!(matches!(most_likely_outcome(&none), Err(Error::InvalidDistribution(_))));
+        let negative = vec![(0, -1., ()), (1, 2., ())];
+        assert!(matches!(sample_outcome(&negative, &mut rng), Err(Error::InvalidDistribution(_))));
+    }
+}
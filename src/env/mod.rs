@@ -1,2 +1,3 @@
 pub mod gym_env;
-pub mod warehouse;
\ No newline at end of file
+pub mod reward;
+pub mod warehouse;
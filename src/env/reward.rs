@@ -0,0 +1,157 @@
+use hashbrown::HashMap;
+use serde::{Serialize, Deserialize};
+use crate::env::warehouse::high_fidelity_warehouse::{Info, Point, State};
+use crate::env::warehouse::action::WarehouseAction;
+use crate::env::warehouse::low_fidelity_warehouse::LowResState;
+
+/// A reward table R(s, a) keyed by (state index, action) with one entry per reward dimension
+pub type RewardTable = HashMap<(i32, i32), Vec<f64>>;
+
+/// A reward model R(s, a) which an environment consults for every state-action pair when it
+/// builds its transition map. A model may have several reward dimensions, the agent stores the
+/// weighted sum of the dimensions because the SCPM has a single cost objective per agent, and the
+/// full vectors are available from the environment's reward table.
+pub trait RewardModel<S> {
+    /// The number of reward dimensions
+    fn dims(&self) -> usize;
+
+    fn reward(&self, state: &S, a: i32, info: &Info) -> Vec<f64>;
+
+    /// The weight of each reward dimension in the scalarised reward
+    fn weights(&self) -> Vec<f64> {
+        vec![1.; self.dims()]
+    }
+
+    fn scalarise(&self, rewards: &[f64]) -> f64 {
+        self.weights().iter().zip(rewards.iter()).map(|(w, r)| w * r).sum()
+    }
+}
+
+/// The same reward for every state and action
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ConstantReward(pub f64);
+
+impl<S> RewardModel<S> for ConstantReward {
+    fn dims(&self) -> usize {
+        1
+    }
+
+    fn reward(&self, _state: &S, _a: i32, _info: &Info) -> Vec<f64> {
+        vec![self.0]
+    }
+}
+
+/// Cost of each high fidelity `WarehouseAction`, both rotations cost `rotate`. The low fidelity
+/// warehouse only moves, and uses the forward cost for every action.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ActionCosts {
+    pub rotate: f64,
+    pub forward: f64,
+    pub pickup: f64,
+    pub place: f64
+}
+
+impl ActionCosts {
+    pub fn uniform(c: f64) -> ActionCosts {
+        ActionCosts {
+            rotate: c,
+            forward: c,
+            pickup: c,
+            place: c
+        }
+    }
+
+    pub fn cost(&self, a: WarehouseAction) -> f64 {
+        match a {
+            WarehouseAction::RotateLeft | WarehouseAction::RotateRight => { self.rotate }
+            WarehouseAction::Forward => { self.forward }
+            WarehouseAction::Pickup => { self.pickup }
+            WarehouseAction::Place => { self.place }
+        }
+    }
+}
+
+/// A warehouse cost model with two reward dimensions: [time, energy].
+/// - time: the cost of the action, plus `carrying_penalty` if the agent is carrying a pack, plus the
+///   cost of any congestion zone the agent is in
+/// - energy: the energy used by the action, plus `carrying_energy` if the agent is carrying a pack
+///
+/// In the low fidelity warehouse the congestion cost of a block is the mean congestion cost of the
+/// cells in the block, and carrying is not modelled. Fields missing from a scenario take the
+/// default values, a time cost of 1 per action which is equivalent to `ConstantReward(1.0)`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct WarehouseRewardModel {
+    pub time: ActionCosts,
+    pub carrying_penalty: f64,
+    pub congestion: Vec<(Point, f64)>,
+    pub energy: ActionCosts,
+    pub carrying_energy: f64,
+    pub weights: [f64; 2]
+}
+
+impl Default for WarehouseRewardModel {
+    fn default() -> Self {
+        WarehouseRewardModel {
+            time: ActionCosts::uniform(1.),
+            carrying_penalty: 0.,
+            congestion: Vec::new(),
+            energy: ActionCosts::uniform(0.),
+            carrying_energy: 0.,
+            weights: [1., 0.]
+        }
+    }
+}
+
+impl WarehouseRewardModel {
+    pub fn congestion_cost(&self, p: &Point) -> f64 {
+        self.congestion.iter()
+            .filter(|(q, _)| q == p)
+            .map(|(_, c)| *c)
+            .sum()
+    }
+}
+
+impl RewardModel<State> for WarehouseRewardModel {
+    fn dims(&self) -> usize {
+        2
+    }
+
+    fn reward(&self, state: &State, a: i32, _info: &Info) -> Vec<f64> {
+        let carrying = state.carrying as f64;
+        // an action outside the action space costs nothing
+        let (time, energy) = match WarehouseAction::from_index(a) {
+            Some(a) => { (self.time.cost(a), self.energy.cost(a)) }
+            None => { (0., 0.) }
+        };
+        let time = time
+            + carrying * self.carrying_penalty
+            + self.congestion_cost(&state.agent_position);
+        let energy = energy + carrying * self.carrying_energy;
+        vec![time, energy]
+    }
+
+    fn weights(&self) -> Vec<f64> {
+        self.weights.to_vec()
+    }
+}
+
+impl RewardModel<LowResState> for WarehouseRewardModel {
+    fn dims(&self) -> usize {
+        2
+    }
+
+    fn reward(&self, state: &LowResState, _a: i32, info: &Info) -> Vec<f64> {
+        let cells = info.grid_abstraction.block_cells(state);
+        let congestion = if cells.is_empty() {
+            0.
+        } else {
+            cells.iter().map(|c| self.congestion_cost(c)).sum::<f64>() / cells.len() as f64
+        };
+        vec![self.time.forward + congestion, self.energy.forward]
+    }
+
+    fn weights(&self) -> Vec<f64> {
+        self.weights.to_vec()
+    }
+}
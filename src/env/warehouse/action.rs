@@ -0,0 +1,216 @@
+use serde::{Serialize, Deserialize};
+use crate::error::Error;
+
+/*
+The solvers index actions and headings by integers, these enums give the integers their meaning.
+The grid y axis points down, so Down moves to y + 1.
+
+Heading:          0 = Right (1, 0), 1 = Down (0, 1), 2 = Left (-1, 0), 3 = Up (0, -1)
+WarehouseAction:  0 = RotateLeft, 1 = RotateRight, 2 = Forward, 3 = Pickup, 4 = Place
+LowResAction:     0 = Left, 1 = Right, 2 = Down, 3 = Up
+*/
+
+/// The heading of an agent, its index is the key of `Info::rotation_mapping`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Heading {
+    Right,
+    Down,
+    Left,
+    Up
+}
+
+impl Heading {
+    pub const ALL: [Heading; 4] = [Heading::Right, Heading::Down, Heading::Left, Heading::Up];
+
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_index(i: u8) -> Option<Heading> {
+        Heading::ALL.get(i as usize).copied()
+    }
+
+    /// The change in position of one step along the heading
+    pub fn offset(self) -> (i32, i32) {
+        match self {
+            Heading::Right => { (1, 0) }
+            Heading::Down => { (0, 1) }
+            Heading::Left => { (-1, 0) }
+            Heading::Up => { (0, -1) }
+        }
+    }
+
+    /// The heading after turning 90 degrees anti-clockwise
+    pub fn rotate_left(self) -> Heading {
+        Heading::ALL[(self.index() as usize + 3) % 4]
+    }
+
+    /// The heading after turning 90 degrees clockwise
+    pub fn rotate_right(self) -> Heading {
+        Heading::ALL[(self.index() as usize + 1) % 4]
+    }
+
+    pub fn reverse(self) -> Heading {
+        Heading::ALL[(self.index() as usize + 2) % 4]
+    }
+
+    /// The snake case name used by the exports
+    pub fn name(self) -> &'static str {
+        match self {
+            Heading::Right => { "right" }
+            Heading::Down => { "down" }
+            Heading::Left => { "left" }
+            Heading::Up => { "up" }
+        }
+    }
+}
+
+impl TryFrom<u8> for Heading {
+    type Error = Error;
+
+    fn try_from(i: u8) -> Result<Self, Self::Error> {
+        Heading::from_index(i).ok_or(Error::UnknownHeading(i))
+    }
+}
+
+impl From<Heading> for u8 {
+    fn from(h: Heading) -> Self {
+        h.index()
+    }
+}
+
+/// The actions of the high fidelity warehouse
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WarehouseAction {
+    RotateLeft,
+    RotateRight,
+    Forward,
+    Pickup,
+    Place
+}
+
+impl WarehouseAction {
+    pub const ALL: [WarehouseAction; 5] = [
+        WarehouseAction::RotateLeft,
+        WarehouseAction::RotateRight,
+        WarehouseAction::Forward,
+        WarehouseAction::Pickup,
+        WarehouseAction::Place
+    ];
+
+    pub fn index(self) -> i32 {
+        self as i32
+    }
+
+    pub fn from_index(a: i32) -> Option<WarehouseAction> {
+        usize::try_from(a).ok().and_then(|i| WarehouseAction::ALL.get(i).copied())
+    }
+
+    /// The snake case name used by the exports
+    pub fn name(self) -> &'static str {
+        match self {
+            WarehouseAction::RotateLeft => { "rotate_left" }
+            WarehouseAction::RotateRight => { "rotate_right" }
+            WarehouseAction::Forward => { "forward" }
+            WarehouseAction::Pickup => { "pickup" }
+            WarehouseAction::Place => { "place" }
+        }
+    }
+}
+
+impl TryFrom<i32> for WarehouseAction {
+    type Error = Error;
+
+    fn try_from(a: i32) -> Result<Self, Self::Error> {
+        WarehouseAction::from_index(a).ok_or(Error::UnknownAction(a))
+    }
+}
+
+impl From<WarehouseAction> for i32 {
+    fn from(a: WarehouseAction) -> Self {
+        a.index()
+    }
+}
+
+/// The actions of the low fidelity warehouse, each moves one block along a heading
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LowResAction {
+    Left,
+    Right,
+    Down,
+    Up
+}
+
+impl LowResAction {
+    pub const ALL: [LowResAction; 4] = [LowResAction::Left, LowResAction::Right, LowResAction::Down, LowResAction::Up];
+
+    pub fn index(self) -> i32 {
+        self as i32
+    }
+
+    pub fn from_index(a: i32) -> Option<LowResAction> {
+        usize::try_from(a).ok().and_then(|i| LowResAction::ALL.get(i).copied())
+    }
+
+    pub fn heading(self) -> Heading {
+        match self {
+            LowResAction::Left => { Heading::Left }
+            LowResAction::Right => { Heading::Right }
+            LowResAction::Down => { Heading::Down }
+            LowResAction::Up => { Heading::Up }
+        }
+    }
+}
+
+impl TryFrom<i32> for LowResAction {
+    type Error = Error;
+
+    fn try_from(a: i32) -> Result<Self, Self::Error> {
+        LowResAction::from_index(a).ok_or(Error::UnknownAction(a))
+    }
+}
+
+impl From<LowResAction> for i32 {
+    fn from(a: LowResAction) -> Self {
+        a.index()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indices_keep_the_scheduler_encoding() {
+        // the integers are written to the scheduler files, they must not change
+        let headings: Vec<(u8, &str)> = Heading::ALL.iter().map(|h| (h.index(), h.name())).collect();
+        assert_eq!(headings, vec![(0, "right"), (1, "down"), (2, "left"), (3, "up")]);
+        let actions: Vec<(i32, &str)> = WarehouseAction::ALL.iter().map(|a| (a.index(), a.name())).collect();
+        assert_eq!(actions, vec![(0, "rotate_left"), (1, "rotate_right"), (2, "forward"), (3, "pickup"), (4, "place")]);
+        let low_res: Vec<(i32, Heading)> = LowResAction::ALL.iter().map(|a| (a.index(), a.heading())).collect();
+        assert_eq!(low_res, vec![(0, Heading::Left), (1, Heading::Right), (2, Heading::Down), (3, Heading::Up)]);
+        for h in Heading::ALL {
+            assert_eq!(Heading::try_from(u8::from(h)).unwrap(), h);
+            assert_eq!(h.rotate_left().rotate_right(), h);
+            assert_eq!(h.rotate_right().rotate_right(), h.reverse());
+            let ((x, y), (rx, ry)) = (h.offset(), h.reverse().offset());
+            assert_eq!((x + rx, y + ry), (0, 0));
+        }
+        assert_eq!(Heading::Right.rotate_right(), Heading::Down);
+        for a in WarehouseAction::ALL {
+            assert_eq!(WarehouseAction::try_from(i32::from(a)).unwrap(), a);
+        }
+        for a in LowResAction::ALL {
+            assert_eq!(LowResAction::try_from(i32::from(a)).unwrap(), a);
+        }
+    }
+
+    #[test]
+    fn out_of_range_indices_are_errors() {
+        assert!(matches!(Heading::try_from(4), Err(Error::UnknownHeading(4))));
+        assert!(matches!(WarehouseAction::try_from(5), Err(Error::UnknownAction(5))));
+        assert!(matches!(WarehouseAction::try_from(-1), Err(Error::UnknownAction(-1))));
+        assert!(matches!(LowResAction::try_from(4), Err(Error::UnknownAction(4))));
+        assert!(LowResAction::from_index(-1).is_none());
+    }
+}
@@ -0,0 +1,256 @@
+use std::io::Write;
+use gif::{Encoder, Frame, Repeat};
+use crate::env::warehouse::action::Heading;
+use crate::env::warehouse::high_fidelity_warehouse::{Info, Point, State};
+use crate::error::Error;
+use crate::scheduler::executor::ExecutionStatus;
+use crate::scheduler::simulator::{JobOutcome, SimulationReport};
+
+/*
+Every frame of an animation is one step of a simulated run, drawn from top to bottom as:
+- a time bar, filled in proportion to the step
+- the warehouse, with the cell colours of the SVG renderer, a mark on the leaving edge of one-way
+  lane cells, packs on the floor, and each agent as a disc in its colour with a black mark on the
+  edge it is heading towards and a rack coloured square when it is carrying a pod
+- a task progress row per agent, one segment per scheduler: green once it completed, red once it
+  failed or had no action, the agent colour while it is running and grey until it starts
+*/
+
+const CORRIDOR: u8 = 0;
+const BLANK: u8 = 1;
+const RACK: u8 = 2;
+const EMPTY_RACK: u8 = 3;
+const FEED: u8 = 4;
+const QUEUE: u8 = 5;
+const CHARGER: u8 = 6;
+const OBSTACLE: u8 = 7;
+const WALL: u8 = 8;
+const NO_STOPPING: u8 = 9;
+const PACK: u8 = 10;
+const GRID: u8 = 11;
+const BLACK: u8 = 12;
+const COMPLETE: u8 = 13;
+const FAILED: u8 = 14;
+const PENDING: u8 = 15;
+const AGENT: u8 = 16;
+const NUM_AGENT_COLOURS: usize = 6;
+
+const PALETTE: [u8; 3 * (AGENT as usize + NUM_AGENT_COLOURS)] = [
+    250, 250, 250, // corridor
+    189, 189, 189, // blank
+    141, 110, 99, // rack
+    215, 204, 200, // empty rack
+    102, 187, 106, // feed
+    79, 195, 247, // queue
+    255, 213, 79, // charger
+    97, 97, 97, // obstacle
+    33, 33, 33, // wall
+    239, 154, 154, // no stopping
+    255, 204, 128, // pack
+    224, 224, 224, // grid
+    0, 0, 0, // black
+    46, 125, 50, // complete
+    183, 28, 28, // failed
+    158, 158, 158, // pending
+    229, 57, 53, // agents, as in the SVG renderer
+    30, 136, 229,
+    67, 160, 71,
+    142, 36, 170,
+    251, 140, 0,
+    0, 137, 123
+];
+
+/// The size and speed of an animation
+#[derive(Clone, Copy, Debug)]
+pub struct AnimationOptions {
+    /// The side of a cell in pixels
+    pub cell: usize,
+    /// The delay between frames in hundredths of a second
+    pub delay: u16
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        AnimationOptions { cell: 16, delay: 20 }
+    }
+}
+
+/// An indexed image in the palette above
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>
+}
+
+impl Canvas {
+    fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, colour: u8) {
+        for py in y..(y + h).min(self.height) {
+            for px in x..(x + w).min(self.width) {
+                self.pixels[py * self.width + px] = colour;
+            }
+        }
+    }
+
+    fn fill_disc(&mut self, cx: usize, cy: usize, r: usize, colour: u8) {
+        let r2 = (r * r) as i64;
+        for py in cy.saturating_sub(r)..(cy + r + 1).min(self.height) {
+            for px in cx.saturating_sub(r)..(cx + r + 1).min(self.width) {
+                let (dx, dy) = (px as i64 - cx as i64, py as i64 - cy as i64);
+                if dx * dx + dy * dy <= r2 {
+                    self.pixels[py * self.width + px] = colour;
+                }
+            }
+        }
+    }
+}
+
+fn symbol_colour(symbol: char) -> u8 {
+    match symbol {
+        'R' | 'I' => { RACK }
+        'E' => { EMPTY_RACK }
+        'F' => { FEED }
+        'Q' => { QUEUE }
+        'C' => { CHARGER }
+        '#' => { OBSTACLE }
+        'W' => { WALL }
+        'X' => { NO_STOPPING }
+        'p' => { PACK }
+        ' ' => { BLANK }
+        _ => { CORRIDOR }
+    }
+}
+
+fn status_colour(outcome: &JobOutcome, step: usize) -> u8 {
+    if step < outcome.start {
+        PENDING
+    } else if step < outcome.end {
+        AGENT + (outcome.agent % NUM_AGENT_COLOURS) as u8
+    } else {
+        match outcome.status {
+            ExecutionStatus::Complete => { COMPLETE }
+            ExecutionStatus::Running => { AGENT + (outcome.agent % NUM_AGENT_COLOURS) as u8 }
+            ExecutionStatus::Failed | ExecutionStatus::NoAction => { FAILED }
+        }
+    }
+}
+
+/// The top left pixel of a mark of side `m` on the edge of the cell at (`x`, `y`) in heading `h`
+fn edge_mark(x: usize, y: usize, cell: usize, m: usize, h: Heading) -> (usize, usize) {
+    let mid = (cell - m) / 2;
+    match h {
+        Heading::Right => { (x + cell - m - 1, y + mid) }
+        Heading::Down => { (x + mid, y + cell - m - 1) }
+        Heading::Left => { (x + 1, y + mid) }
+        Heading::Up => { (x + mid, y + 1) }
+    }
+}
+
+impl<'a> Info<'a> {
+    /// Write `report` as an animated GIF which loops forever, with one frame per step of the run
+    pub fn simulation_gif<W: Write>(
+        &self,
+        report: &SimulationReport,
+        options: &AnimationOptions,
+        out: W
+    ) -> Result<(), Error> {
+        let cell = options.cell.max(4);
+        let bar = (cell / 2).max(2);
+        let agents = report.trajectories.len();
+        let width = self.width * cell;
+        let height = bar + self.height * cell + agents * bar;
+        if width > u16::MAX as usize || height > u16::MAX as usize {
+            return Err(Error::Render(format!(
+                "a {}x{} pixel animation is too large for a GIF, use a smaller cell size", width, height
+            )))
+        }
+        let mut encoder = Encoder::new(out, width as u16, height as u16, &PALETTE[..])?;
+        encoder.set_repeat(Repeat::Infinite)?;
+        let frames = report.trajectories.iter().map(|t| t.len()).max().unwrap_or(0);
+        for step in 0..frames {
+            // agents which stopped early stay in their last state
+            let states: Vec<State> = report.trajectories.iter()
+                .filter_map(|t| t.get(step).or_else(|| t.last()).copied())
+                .collect();
+            let canvas = self.draw_frame(report, &states[..], step, frames, cell, bar);
+            let mut frame = Frame::from_indexed_pixels(width as u16, height as u16, &canvas.pixels[..], None);
+            frame.delay = options.delay;
+            encoder.write_frame(&frame)?;
+        }
+        Ok(())
+    }
+
+    fn draw_frame(
+        &self,
+        report: &SimulationReport,
+        states: &[State],
+        step: usize,
+        frames: usize,
+        cell: usize,
+        bar: usize
+    ) -> Canvas {
+        let width = self.width * cell;
+        let grid_height = self.height * cell;
+        let mut canvas = Canvas {
+            width,
+            height: bar + grid_height + states.len() * bar,
+            pixels: vec![GRID; width * (bar + grid_height + states.len() * bar)]
+        };
+        // time bar
+        canvas.fill_rect(0, 0, width * (step + 1) / frames.max(1), bar - 1, PENDING);
+        // warehouse
+        let mut packs: Vec<Point> = states.iter().flat_map(|s| s.floor_packs()).collect();
+        packs.sort_unstable();
+        packs.dedup();
+        let empty_racks: Vec<Point> = if states.is_empty() {
+            self.empty_rack_slots.to_vec()
+        } else {
+            states.iter().flat_map(|s| s.empty_rack_slots()).collect()
+        };
+        let m = (cell / 4).max(1);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let p: Point = (x as i32, y as i32);
+                let (px, py) = (x * cell, bar + y * cell);
+                let symbol = self.cell_symbol(&p, &packs[..], &empty_racks[..]);
+                canvas.fill_rect(px, py, cell - 1, cell - 1, symbol_colour(symbol));
+                let lane = match symbol {
+                    '>' => { Some(Heading::Right) }
+                    'v' => { Some(Heading::Down) }
+                    '<' => { Some(Heading::Left) }
+                    '^' => { Some(Heading::Up) }
+                    _ => { None }
+                };
+                if let Some(h) = lane {
+                    let (mx, my) = edge_mark(px, py, cell, m, h);
+                    canvas.fill_rect(mx, my, m, m, GRID);
+                }
+            }
+        }
+        for (i, state) in states.iter().enumerate() {
+            let (px, py) = (state.agent_position.0 as usize * cell, bar + state.agent_position.1 as usize * cell);
+            let colour = AGENT + (i % NUM_AGENT_COLOURS) as u8;
+            canvas.fill_disc(px + cell / 2, py + cell / 2, cell * 3 / 8, colour);
+            if state.carrying == 1 {
+                canvas.fill_rect(px + (cell - 2 * m) / 2, py + (cell - 2 * m) / 2, 2 * m, 2 * m, RACK);
+            }
+            if let Some(h) = Heading::from_index(state.agent_dir) {
+                let (mx, my) = edge_mark(px, py, cell, m, h);
+                canvas.fill_rect(mx, my, m, m, BLACK);
+            }
+        }
+        // task progress
+        for agent in 0..states.len() {
+            let mut jobs: Vec<&JobOutcome> = report.outcomes.iter().filter(|o| o.agent == agent).collect();
+            jobs.sort_by_key(|o| o.start);
+            if jobs.is_empty() {
+                continue;
+            }
+            let y = bar + grid_height + agent * bar;
+            let segment = width / jobs.len();
+            for (j, outcome) in jobs.iter().enumerate() {
+                canvas.fill_rect(j * segment, y + 1, segment.saturating_sub(1), bar - 1, status_colour(outcome, step));
+            }
+        }
+        canvas
+    }
+}
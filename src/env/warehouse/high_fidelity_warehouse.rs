@@ -5,45 +5,85 @@ use scpm::agent::{Agent, Robot};
 use hashbrown::HashMap;
 use serde::{Serialize, Deserialize};
 use crate::env::gym_env::Env;
+use crate::env::reward::RewardModel;
+use crate::error::Error;
+use crate::env::warehouse::action::{Heading, WarehouseAction};
+use crate::env::warehouse::low_fidelity_warehouse::{GridAbstraction, LowResNoise};
+use crate::env::warehouse::traffic::TrafficRules;
 
 pub type Point = (i32, i32);
 
-/*
-Directions:
-1 = right
-2 = Down
-3 = Left
-4 = Up
-*/
+/// The maximum number of pods which a state can record as dropped on the floor, and the maximum
+/// number of empty rack slots
+pub const MAX_PODS: usize = 4;
+/// Placeholder for an unused pack or empty rack slot entry
+pub const NO_POINT: Point = (-1, -1);
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct TaskActionPair {
-    pub agent_dir: u8, // {1: Right, 2: Down, 3: Left, 4: Up}
+    pub agent_dir: Heading,
     pub agent_position: Point, // any position on the available grid (x, y)
     pub carrying: u8, // {0, 1}
-    pub pack_available: u8, // {0, 1}
-    pub pack_position: Point,
-    pub action: i32,
+    pub packs: Vec<Point>, // positions of the packs dropped on the floor
+    #[serde(default)]
+    pub empty_racks: Vec<Point>, // tracked rack slots which are empty
+    #[serde(default)]
+    pub battery: u8, // discretised battery level, 0 when the battery is not modelled
+    pub action: WarehouseAction,
     pub q: i32
 }
 
+impl TaskActionPair {
+    pub fn new(state: &State, action: WarehouseAction, q: i32) -> Result<Self, Error> {
+        Ok(TaskActionPair {
+            agent_dir: Heading::try_from(state.agent_dir)?,
+            agent_position: state.agent_position,
+            carrying: state.carrying,
+            packs: state.floor_packs().collect(),
+            empty_racks: state.empty_rack_slots().collect(),
+            battery: state.battery,
+            action,
+            q
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum CellType {
     OutOfBounds,
     Free,
     Pack,
     Rack,
-    Feed
+    Feed,
+    Charger,
+    Queue,
+    Obstacle, // a pillar or other static obstruction inside the site
+    Wall
+}
+
+impl Default for CellType {
+    fn default() -> Self {
+        CellType::Free
+    }
+}
+
+impl CellType {
+    /// Can an agent drive onto a cell of this type
+    pub fn is_drivable(&self) -> bool {
+        matches!(self, CellType::Free | CellType::Charger | CellType::Queue)
+    }
 }
 
 #[derive(Hash, Eq, PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct State {
-    pub agent_dir: u8, // {1: Right, 2: Down, 3: Left, 4: Up}
+    pub agent_dir: u8, // the index of the agent's `Heading` {0: Right, 1: Down, 2: Left, 3: Up}
     pub agent_position: Point, // any position on the available grid (x, y)
     pub carrying: u8, // {0, 1}
-    pub pack_available: u8, // {0, 1}
-    pub pack_position: Point // any position on the available grid
-    // that is not the agent position, (-1, -1) means is the default value
-    // and we look at the pack available first
+    pub packs: [Point; MAX_PODS], // positions of the packs dropped on the floor, sorted with
+    // unused entries set to NO_POINT at the end so that equal sets of packs are equal states
+    pub empty_racks: [Point; MAX_PODS], // tracked rack slots which are currently empty, kept in
+    // the same order as packs
+    pub battery: u8 // discretised battery level, always 0 when the battery is not modelled
 }
 
 impl Default for State {
@@ -52,18 +92,93 @@ impl Default for State {
             agent_dir: 0,
             agent_position: (1, 0),
             carrying: 0,
-            pack_available: 0,
-            pack_position: (-1, -1)
+            packs: [NO_POINT; MAX_PODS],
+            empty_racks: [NO_POINT; MAX_PODS],
+            battery: 0
         }
     }
 }
 
+/// Insert a point into a sorted, NO_POINT padded set, returns false if the set is full
+fn insert_point(set: &mut [Point; MAX_PODS], p: Point) -> bool {
+    if set.contains(&p) {
+        return true
+    }
+    match set.iter().position(|x| *x == NO_POINT) {
+        Some(i) => {
+            set[i] = p;
+            set.sort_by_key(|x| (*x == NO_POINT, *x));
+            true
+        }
+        None => { false }
+    }
+}
+
+fn remove_point(set: &mut [Point; MAX_PODS], p: &Point) {
+    for x in set.iter_mut() {
+        if x == p {
+            *x = NO_POINT;
+        }
+    }
+    set.sort_by_key(|x| (*x == NO_POINT, *x));
+}
+
+impl State {
+    pub fn floor_packs(&self) -> impl Iterator<Item=Point> + '_ {
+        self.packs.iter().copied().filter(|p| *p != NO_POINT)
+    }
+
+    pub fn num_floor_packs(&self) -> usize {
+        self.floor_packs().count()
+    }
+
+    pub fn has_pack_at(&self, p: &Point) -> bool {
+        *p != NO_POINT && self.packs.contains(p)
+    }
+
+    /// Drop a pack on the floor, returns false if no more packs can be recorded
+    pub fn add_pack(&mut self, p: Point) -> bool {
+        insert_point(&mut self.packs, p)
+    }
+
+    pub fn remove_pack(&mut self, p: &Point) {
+        remove_point(&mut self.packs, p)
+    }
+
+    pub fn empty_rack_slots(&self) -> impl Iterator<Item=Point> + '_ {
+        self.empty_racks.iter().copied().filter(|p| *p != NO_POINT)
+    }
+
+    pub fn is_rack_empty(&self, p: &Point) -> bool {
+        *p != NO_POINT && self.empty_racks.contains(p)
+    }
+
+    /// Mark a tracked rack slot as empty, returns false if no more empty slots can be recorded
+    pub fn empty_rack(&mut self, p: Point) -> bool {
+        insert_point(&mut self.empty_racks, p)
+    }
+
+    pub fn fill_rack(&mut self, p: &Point) {
+        remove_point(&mut self.empty_racks, p)
+    }
+
+    /// The number of pods which are not in a rack: the pod being carried and the packs on the floor
+    pub fn pods_off_rack(&self) -> usize {
+        self.carrying as usize + self.num_floor_packs()
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct WarehouseWord {
     pub agent_position: Point,
     pub dir: u8,
     pub carrying: u8,
-    pub pack_position: Option<Point>
+    pub pack_positions: Vec<Point>,
+    pub empty_racks: Vec<Point>,
+    pub battery: Option<u8>,
+    pub at_charger: bool,
+    pub at_queue: bool,
+    pub facing: CellType // the type of the cell in front of the agent
 }
 
 impl WarehouseWord {
@@ -75,16 +190,123 @@ impl WarehouseWord {
         &self.carrying
     }
 
-    pub fn new(pos: Point, dir: u8, carrying: u8, packpos: Option<Point>) -> Self {
+    pub fn new(pos: Point, dir: u8, carrying: u8, packs: Vec<Point>) -> Self {
         WarehouseWord {
             agent_position: pos,
             dir,
             carrying,
-            pack_position: packpos
+            pack_positions: packs,
+            empty_racks: Vec::new(),
+            battery: None,
+            at_charger: false,
+            at_queue: false,
+            facing: CellType::Free
+        }
+    }
+
+    pub fn from_state(state: &State, info: &Info) -> Self {
+        let mut word = WarehouseWord::new(
+            state.agent_position,
+            state.agent_dir,
+            state.carrying,
+            state.floor_packs().collect()
+        );
+        word.empty_racks = state.empty_rack_slots().collect();
+        if info.battery.is_some() {
+            word.battery = Some(state.battery);
+        }
+        word.at_charger = info.charger_positions.contains(&state.agent_position);
+        word.at_queue = info.queue_points.contains(&state.agent_position);
+        // a state with an unknown heading faces nothing, stepping it fails with the heading error
+        word.facing = fwd_cell(
+            front_pos(&state.agent_position, &state.agent_dir, info.rotation_mapping, info.width, info.height)
+                .unwrap_or(None),
+            &state.packs,
+            info
+        );
+        word
+    }
+
+    /// DFA guard for battery constraints, true if the battery is at least `percent` full or the
+    /// battery is not modelled
+    pub fn battery_at_least(&self, info: &Info, percent: f64) -> bool {
+        match (self.battery, &info.battery) {
+            (Some(level), Some(battery)) => { battery.percentage(level) >= percent }
+            _ => { true }
+        }
+    }
+}
+
+/// A discretised battery with levels 0..levels, the battery is full at `levels - 1`. Each action
+/// `a` uses `consumption[a.index()]` levels (0 if not listed) and an agent without enough charge for an
+/// action can not perform it, `step` reports the action as not enabled so it is left out of the
+/// model. Any action other than forward taken while standing on a charger recharges `charge_rate`
+/// levels instead of using charge. `reserve` is the percentage the recharge task must not drop below.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BatteryModel {
+    pub levels: u8,
+    pub consumption: Vec<u8>,
+    pub charge_rate: u8,
+    #[serde(default)]
+    pub reserve: f64
+}
+
+impl BatteryModel {
+    pub fn full(&self) -> u8 {
+        self.levels.saturating_sub(1)
+    }
+
+    pub fn consumption(&self, a: WarehouseAction) -> u8 {
+        self.consumption.get(a.index() as usize).copied().unwrap_or(0)
+    }
+
+    pub fn charge(&self, level: u8) -> u8 {
+        level.saturating_add(self.charge_rate).min(self.full())
+    }
+
+    pub fn percentage(&self, level: u8) -> f64 {
+        if self.full() == 0 {
+            100.
+        } else {
+            100. * level as f64 / self.full() as f64
         }
     }
 }
 
+/// Action outcome uncertainty in the high fidelity warehouse, every probability is the chance that
+/// an action does something other than what was intended. The default model is deterministic.
+/// - slip_stay: a forward move leaves the agent where it is
+/// - slip_drift: a forward move ends in the cell to the left or right of the cell in front of the
+///   agent, split evenly between the two sides
+/// - pickup_fail: a pickup leaves the agent and the pack unchanged
+/// - drop_fail: a place leaves the agent carrying the pack
+/// - rotation_overshoot: a rotation turns the agent through 180 degrees
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct MotionModel {
+    #[serde(default)]
+    pub slip_stay: f64,
+    #[serde(default)]
+    pub slip_drift: f64,
+    #[serde(default)]
+    pub pickup_fail: f64,
+    #[serde(default)]
+    pub drop_fail: f64,
+    #[serde(default)]
+    pub rotation_overshoot: f64
+}
+
+impl MotionModel {
+    pub fn is_valid(&self) -> bool {
+        let probs = [self.slip_stay, self.slip_drift, self.pickup_fail, self.drop_fail, self.rotation_overshoot];
+        probs.iter().all(|p| (0. ..=1.).contains(p)) && self.slip_stay + self.slip_drift <= 1.
+    }
+
+    /// The probability that a possible forward move actually advances the agent one cell
+    pub fn forward_success(&self) -> f64 {
+        1. - self.slip_stay - self.slip_drift
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct RobotMetaData {
     pub feed_points: Vec<Point>,
@@ -101,7 +323,20 @@ pub struct Info<'a> {
     pub width: usize,
     pub height: usize,
     pub rotation_mapping: &'a mut HashMap<u8, (i32, i32)>,
-    pub queue_point: Point
+    pub queue_point: Point,
+    pub queue_points: Vec<Point>,
+    pub agent_start_positions: Vec<Point>,
+    pub obstacle_positions: Vec<Point>,
+    pub wall_positions: Vec<Point>,
+    pub traffic: TrafficRules,
+    pub motion_model: MotionModel,
+    pub low_res_noise: LowResNoise,
+    pub grid_abstraction: GridAbstraction,
+    pub battery: Option<BatteryModel>,
+    pub charger_positions: Vec<Point>,
+    pub max_pods_off_rack: usize,
+    pub inventory_racks: Vec<Point>,
+    pub empty_rack_slots: Vec<Point>
 }
 
 impl<'a> Info<'a> {
@@ -122,7 +357,76 @@ impl<'a> Info<'a> {
             width: w,
             height: h,
             rotation_mapping,
-            queue_point: (0, 0)
+            queue_point: (0, 0),
+            queue_points: Vec::new(),
+            agent_start_positions: Vec::new(),
+            obstacle_positions: Vec::new(),
+            wall_positions: Vec::new(),
+            traffic: Default::default(),
+            motion_model: Default::default(),
+            low_res_noise: Default::default(),
+            grid_abstraction: GridAbstraction::new(w as i32, h as i32, 1),
+            battery: None,
+            charger_positions: Vec::new(),
+            max_pods_off_rack: 1,
+            inventory_racks: Vec::new(),
+            empty_rack_slots: Vec::new()
+        }
+    }
+
+    /// An agent state at `position` facing `dir` with nothing carried, no packs on the floor, the
+    /// initially empty rack slots and a full battery
+    pub fn initial_state(&self, position: Point, dir: u8) -> State {
+        let mut state = State {
+            agent_dir: dir,
+            agent_position: position,
+            battery: self.initial_battery(),
+            ..Default::default()
+        };
+        for slot in self.empty_rack_slots.iter() {
+            state.empty_rack(*slot);
+        }
+        state
+    }
+
+    /// Obstacles and walls can never be occupied
+    pub fn is_blocked(&self, p: &Point) -> bool {
+        self.obstacle_positions.contains(p) || self.wall_positions.contains(p)
+    }
+
+    /// The type of the cell at `p` given the packs on the floor
+    pub fn cell_type(&self, p: &Point, packs: &[Point]) -> CellType {
+        if p.0 < 0 || p.1 < 0 || p.0 >= self.width as i32 || p.1 >= self.height as i32 {
+            CellType::OutOfBounds
+        } else if self.wall_positions.contains(p) {
+            CellType::Wall
+        } else if self.obstacle_positions.contains(p) {
+            CellType::Obstacle
+        } else if self.rack_positions.contains(p) {
+            CellType::Rack
+        } else if packs.contains(p) {
+            CellType::Pack
+        } else if self.feed_points.contains(p) {
+            CellType::Feed
+        } else if self.charger_positions.contains(p) {
+            CellType::Charger
+        } else if self.queue_points.contains(p) {
+            CellType::Queue
+        } else {
+            CellType::Free
+        }
+    }
+
+    /// Rack slots can only be emptied and filled if their occupancy is tracked
+    pub fn is_inventory_rack(&self, p: &Point) -> bool {
+        self.inventory_racks.contains(p)
+    }
+
+    /// The battery level an agent starts with, a full battery or 0 when the battery is not modelled
+    pub fn initial_battery(&self) -> u8 {
+        match &self.battery {
+            Some(battery) => { battery.full() }
+            None => { 0 }
         }
     }
     
@@ -149,6 +453,15 @@ impl<'a> Info<'a> {
         }
     }
     
+    /// Set the traffic rules, with `None` the one-way aisles of the original warehouse are
+    /// generated from the rack positions, so the racks should be set first
+    pub fn set_traffic_rules(&mut self, input: Option<TrafficRules>) {
+        match input {
+            Some(rules) => { self.traffic = rules; }
+            None => { self.traffic = TrafficRules::parity_aisles(&self.rack_positions[..]); }
+        }
+    }
+
     pub fn set_corridors(&mut self, input: Option<Vec<Point>>) {
         match input {
             Some(mut x) => {
@@ -159,11 +472,16 @@ impl<'a> Info<'a> {
                 let gy: Vec<i32> = (0..self.height as i32).collect();
                 let rack_positions = self.rack_positions.to_vec();
                 let feed = self.feed_points.clone();
+                let blocked: Vec<Point> = self.obstacle_positions.iter()
+                    .chain(self.wall_positions.iter())
+                    .copied()
+                    .collect();
                 for (x, y) in gx.into_iter()
                     .cartesian_product(gy.into_iter())
                     .filter(|grid: &Point|
-                        !(rack_positions.iter().any(|p| grid == p) && feed.iter().any(|p| grid == p))
-                    ) {
+                        !rack_positions.iter().any(|p| grid == p) && !feed.iter().any(|p| grid == p)
+                    )
+                    .filter(|grid: &Point| !blocked.iter().any(|p| grid == p)) {
                     self.corridor_positions.push((x as i32, y as i32));
                 }
             }
@@ -178,11 +496,15 @@ impl<'a> Info<'a> {
         &self.lookup_rack
     }
     
+    /// Set the number of high resolution cells along each side of a low resolution block
+    pub fn set_grid_square(&mut self, grid_square: usize) {
+        self.grid_abstraction = GridAbstraction::new(self.width as i32, self.height as i32, grid_square);
+    }
+
     pub fn set_rotation_mapping(&mut self) {
-        self.rotation_mapping.insert(0, (1, 0)); // right
-        self.rotation_mapping.insert(1, (0, 1)); // down
-        self.rotation_mapping.insert(2, (-1, 0)); // left
-        self.rotation_mapping.insert(3, (0, -1)); // up
+        for heading in Heading::ALL {
+            self.rotation_mapping.insert(heading.index(), heading.offset());
+        }
     }
 }
 
@@ -193,54 +515,45 @@ pub fn warehouse_defaults() -> (Vec<Point>, Vec<Point>, HashMap<u8, (i32, i32)>)
     (rack_positions, corridor_positions, rotation_mapping)
 }
 
+/// The cell in front of an agent at `agent_position` facing `dir`, `None` if it is off the grid.
+/// Fails if `dir` is not a heading or the rotation mapping has no offset for it.
 pub fn front_pos(
     agent_position: &Point,
     dir: &u8,
     rotation_mapping: &HashMap<u8, (i32, i32)>,
     w: usize,
     h: usize
-) -> Option<Point> {
+) -> Result<Option<Point>, Error> {
     // rx is the x rotation
     // ry is the y rotation
     let (ax, ay) = agent_position;
-    let (rx, ry) = match rotation_mapping.get(dir) {
-        Some(p) => *p,
-        None => {panic!("dir: {}, not found", dir)}
-    };
+    let heading = Heading::try_from(*dir)?;
+    let (rx, ry) = rotation_mapping.get(&heading.index())
+        .copied()
+        .ok_or_else(|| Error::InvalidModel(format!("the rotation mapping has no heading {}", heading.index())))?;
     let x = ax + rx;
     let y = ay + ry;
     if x >= 0 && y >= 0 && x < w as i32 && y < h as i32 {
-        Some((x, y))
+        Ok(Some((x, y)))
     } else {
-        None
+        Ok(None)
     }
 }
 
-pub fn fwd_cell(
-    p: Option<Point>,
-    pack_available: u8,
-    pack_point: &Point,
-    rack_positions: &[Point],
-    feedpoint: &[Point]
-) -> CellType {
+/// The type of the cell the agent faces, `p` is the front position from `front_pos`
+pub fn fwd_cell(p: Option<Point>, packs: &[Point], info: &Info) -> CellType {
     match p {
         None => { CellType::OutOfBounds }
-        Some(point) => {
-            // does the agent face a pack, rack, or free position
-            if rack_positions.iter().any(|x| *x == point) {
-                CellType::Rack
-            } else if pack_available == 1 && *pack_point == point {
-                CellType::Pack
-            } else if feedpoint.iter().any(|&p| p == point) {
-                CellType::Feed
-            } else {
-                CellType::Free
-            }
-        }
+        Some(point) => { info.cell_type(&point, packs) }
     }
 }
 
-impl Env<Robot<State, WarehouseWord>, State, WarehouseWord> for Robot<State, WarehouseWord> {
+impl Env for Robot<State, WarehouseWord> {
+    type State = State;
+    type Word = WarehouseWord;
+    type Context<'c> = Info<'c>;
+    type Rewards = dyn RewardModel<State>;
+
     fn make(na: i32, init_state: State) -> Robot<State, WarehouseWord> {
         Robot {
             states: vec![],
@@ -255,254 +568,230 @@ impl Env<Robot<State, WarehouseWord>, State, WarehouseWord> for Robot<State, War
         }
     }
 
-    fn state_space(&mut self) {
-        // we can't use this state space generation becuase it won't have all of the information
-        // that is required. Therefore we will need to use a super trait and calll that from the
-        // binary. The super trait is defined below
-    }
-
-    fn step(&mut self, _state: &State, _a: i32) -> Result<Vec<(State, f64, WarehouseWord)>, &'static str> {
-        Ok(vec![])
-    }
-
-    fn transition_map(&mut self, _r: &f64) { }
-}
-
-pub trait WarehouseEnv: Env<Robot<State, WarehouseWord>, State, WarehouseWord> + Agent<State, WarehouseWord> {
-    fn warehouse_make(na: i32, init_state: State) -> Robot<State, WarehouseWord> {
-        Self::make(na, init_state)
-    }
-
-    fn warehouse_state_space(
-        &mut self,
-        corridor_positions: &[Point],
-    ) {
-        let mut state_counter: usize = 0;
+    fn state_space(&mut self, info: &Info) {
+        // obstacles and walls are never occupied, even if they were given as corridors
+        let corridor_positions: Vec<Point> = info.corridor_positions.iter()
+            .filter(|p| !info.is_blocked(p))
+            .copied()
+            .collect();
+        let max_pods = info.max_pods_off_rack.min(MAX_PODS);
+        // every combination of tracked rack slots which could be empty, a slot is only emptied by
+        // picking up from it, so at most MAX_PODS slots can be empty at once
+        let max_empty = info.inventory_racks.len().min(MAX_PODS);
+        let rack_configs: Vec<Vec<Point>> = (0..=max_empty)
+            .flat_map(|k| info.inventory_racks.iter().copied().combinations(k))
+            .collect();
+        let mut states: Vec<State> = Vec::new();
         for p in corridor_positions.iter() {
+            // packs can be dropped on any corridor position that the agent does not occupy
+            let remaining_corridor_positions: Vec<Point> = corridor_positions.iter()
+                .filter(|p2| *p2 != p)
+                .copied()
+                .collect();
             for dir in 0..4u8 {
-                // this is all of the positions that the agent could occupy
-                // place the agent in one of these positions
-                // now that this position has been taken up by the agent
-                // - the agent may or may not be carrying something
                 for c in 0..2u8 {
-                    match c {
-                        0 => {
-                            // the agent is not carrying anything and therefore
-                            // it is possible that a pack could be placed somewhere
-                            // on the grid
-                            // get the remaining positions
-                            let remaining_corridor_positions =
-                                corridor_positions.iter().filter(|p2| *p2 != p);
-                            for p2 in remaining_corridor_positions {
+                    if c as usize > max_pods {
+                        continue;
+                    }
+                    // the pod being carried counts against the pods which may be off a rack
+                    let max_floor_packs = max_pods - c as usize;
+                    for k in 0..=max_floor_packs {
+                        for packs in remaining_corridor_positions.iter().copied().combinations(k) {
+                            for empty_racks in rack_configs.iter() {
                                 let mut state: State = Default::default();
                                 state.agent_position = *p;
-                                state.carrying = 0;
-                                state.pack_available = 1;
-                                state.pack_position = *p2;
                                 state.agent_dir = dir;
-                                self.set_state(&state);
-                                self.insert_state_mapping(&state, state_counter);
-                                self.insert_word(WarehouseWord::new(
-                                    state.agent_position, 
-                                    state.agent_dir, 
-                                    state.carrying,
-                                    Some(state.pack_position) 
-                                ));
-                                state_counter += 1;
+                                state.carrying = c;
+                                for pack in packs.iter() {
+                                    state.add_pack(*pack);
+                                }
+                                for slot in empty_racks.iter() {
+                                    state.empty_rack(*slot);
+                                }
+                                states.push(state);
                             }
-                            // Initial situations where the agent is not carrying a pack and no pack has
-                            // been placed on the grid
-                            let mut state: State = Default::default();
-                            state.agent_position = *p;
-                            state.carrying = 0;
-                            state.agent_dir = dir;
-                            self.set_state(&state);
-                            self.insert_state_mapping(&state, state_counter);
-                            self.insert_word(WarehouseWord::new(
-                                state.agent_position, 
-                                state.agent_dir, 
-                                state.carrying, 
-                                None
-                            ));
-                            state_counter += 1;
-                        }
-                        1 => {
-                            // the agent is carrying something, and we assume that
-                            // the agent has not yet dropped the item somewhere
-                            // on the grid; meaning that there is no pack
-                            let mut state: State = Default::default();
-                            state.agent_position = *p;
-                            state.agent_dir = dir;
-                            state.carrying = 1;
-                            self.set_state(&state);
-                            self.insert_state_mapping(&state, state_counter);
-                            self.insert_word(WarehouseWord::new(
-                                state.agent_position, 
-                                state.agent_dir, 
-                                state.carrying,
-                                None
-                            ));
-                            state_counter += 1;
-                        }
-                        _ => {
-                            // this should not be possible, we could throw an error
-                            // but it does not need to be handled
                         }
                     }
                 }
             }
         }
+        // every configuration can occur at any battery level
+        let battery_levels: Vec<u8> = match &info.battery {
+            Some(battery) => { (0..battery.levels).collect() }
+            None => { vec![0] }
+        };
+        let mut state_counter: usize = 0;
+        for base_state in states.iter() {
+            for level in battery_levels.iter() {
+                let mut state = *base_state;
+                state.battery = *level;
+                self.set_state(&state);
+                self.insert_state_mapping(&state, state_counter);
+                self.insert_word(WarehouseWord::from_state(&state, info));
+                state_counter += 1;
+            }
+        }
         self.set_reverse_state_mapping();
     }
 
-    fn warehouse_step(&self, state: &State, a: i32, info: &Info, max_x: &i32, min_y: &i32, max_y: &i32)
-        -> Result<Vec<(State, f64, WarehouseWord)>, &'static str> {
-        let mut new_dir = state.agent_dir;
-        let mut new_agent_positions: Point = state.agent_position;
-        let mut new_carrying = state.carrying;
-        let mut is_pack = state.pack_available;
-        let mut pack_position = state.pack_position;
+    fn step(&self, state: &State, action: i32, info: &Info)
+        -> Result<Vec<(State, f64, WarehouseWord)>, Error> {
+        // the models of `info` are checked once by `Info::validate_model`
+        let a = WarehouseAction::try_from(action)?;
+        let motion = &info.motion_model;
+        let heading = Heading::try_from(state.agent_dir)?;
+        // pods can not be handled in a no-stopping zone
+        let no_stopping = info.traffic.is_no_stopping(&state.agent_position);
         let fwd_position = front_pos(
             &state.agent_position,
             &state.agent_dir, info.rotation_mapping, info.width, info.height
-        );
-        let fwd_cell = fwd_cell(
-            fwd_position,
-            is_pack,
-            &pack_position,
-            &info.rack_positions[..],
-            &info.feed_points
-        );
+        )?;
+        let fwd_cell = fwd_cell(fwd_position, &state.packs, info);
+        // the intended outcome of an action is listed first, followed by any unintended outcomes
+        let mut outcomes: Vec<(State, f64)> = Vec::new();
         match a {
-            // rotate left
-            0 => {
-                if new_dir == 0 {
-                    new_dir += 3
-                } else {
-                    new_dir -= 1;
-                }
-                //new_dir -= 1;
-                //if new_dir < 0 {
-                //    new_dir += 4;
-                //}
-                //println!("new dir: {:?}", new_dir);
+            WarehouseAction::RotateLeft => {
+                let mut new_state = *state;
+                new_state.agent_dir = heading.rotate_left().index();
+                outcomes.push((new_state, 1.0 - motion.rotation_overshoot));
+                // overshooting a rotation turns the agent around
+                new_state.agent_dir = heading.reverse().index();
+                outcomes.push((new_state, motion.rotation_overshoot));
             }
-            // rotate right
-            1 => {
-                new_dir = (new_dir + 1) % 4;
+            WarehouseAction::RotateRight => {
+                let mut new_state = *state;
+                new_state.agent_dir = heading.rotate_right().index();
+                outcomes.push((new_state, 1.0 - motion.rotation_overshoot));
+                new_state.agent_dir = heading.reverse().index();
+                outcomes.push((new_state, motion.rotation_overshoot));
             }
-            // go forward
-            2 => {
-                match fwd_cell {
-                    CellType::Free => {
-                        // go forward
-                        let (px, py) = state.agent_position;
-                        let (_, fy) = fwd_position.unwrap();
-                        //if px >= *min_x + 2 && px <= *max_x - 2 && py >= *min_y && py <= *max_y && px % 2 != 0 {
-                        if px <= *max_x - 2 && py >= *min_y && py <= *max_y && px % 2 != 0 {
-                            // odds only go down
-                            if fy < py || fy == py {
-                                new_agent_positions = fwd_position.unwrap();
-                            }
-                        } //else if px >= *min_x + 2 && px <= *max_x - 2 && py >= *min_y && py <= *max_y && px % 2 == 0 {
-                        else if px <= *max_x - 2 && py >= *min_y && py <= *max_y && px % 2 == 0 {
-                            if fy > py || fy == py {
-                                new_agent_positions = fwd_position.unwrap();
-                            }
-                        } else {
-                            new_agent_positions = fwd_position.unwrap();
+            WarehouseAction::Forward => {
+                let mut new_agent_positions: Point = state.agent_position;
+                // chargers and queue points are parking cells which can be driven onto, go forward
+                // if the traffic rules allow the heading
+                if fwd_cell.is_drivable() && info.traffic.allows(&state.agent_position, state.agent_dir) {
+                    new_agent_positions = fwd_position.unwrap();
+                }
+                if new_agent_positions == state.agent_position {
+                    // the move is not possible, the agent does not move
+                    outcomes.push((*state, 1.0));
+                } else {
+                    let mut new_state = *state;
+                    new_state.agent_position = new_agent_positions;
+                    outcomes.push((new_state, 1.0 - motion.slip_stay - motion.slip_drift));
+                    outcomes.push((*state, motion.slip_stay));
+                    // drifting moves the agent to a free cell either side of the cell in front of
+                    // it, if the drift cell is not free or the traffic rules do not allow the
+                    // sideways move then the agent is stopped where it is
+                    for side in [heading.rotate_left(), heading.rotate_right()] {
+                        let drift_position = front_pos(
+                            &new_agent_positions, &side.index(), info.rotation_mapping, info.width, info.height
+                        )?;
+                        let drift_cell = self::fwd_cell(drift_position, &state.packs, info);
+                        let mut drift_state = *state;
+                        if drift_cell.is_drivable() && info.traffic.allows(&new_agent_positions, side.index()) {
+                            drift_state.agent_position = drift_position.unwrap();
                         }
-
+                        outcomes.push((drift_state, motion.slip_drift / 2.0));
                     }
-                    _ => { }
                 }
             }
-            // pickup
-            3 => {
-                if state.carrying == 0 {
+            WarehouseAction::Pickup => {
+                let mut new_state = *state;
+                // a pod can only be taken off a rack or the feed if the number of pods off the
+                // racks stays within the limit
+                let can_take = state.pods_off_rack() < info.max_pods_off_rack;
+                if state.carrying == 0 && !no_stopping {
                     match fwd_cell {
                         CellType::Feed => {
-                            // only pick up from feed if there is currenlty not a pack on the floor
-                            if state.pack_available == 0 {
-                                new_carrying = 1;
+                            if can_take {
+                                new_state.carrying = 1;
                             }
                         }
                         CellType::Pack => {
-                            new_carrying = 1;
-                            is_pack = 0;
-                            pack_position = (-1, - 1);
+                            new_state.carrying = 1;
+                            new_state.remove_pack(&fwd_position.unwrap());
                         }
                         CellType::Rack => {
-                            // only pick up from the rack if there is a no pack on the floor
-                            if state.pack_available == 0 {
-                                new_carrying = 1;
+                            let rack = fwd_position.unwrap();
+                            if info.is_inventory_rack(&rack) {
+                                // a tracked slot must hold a pod, and becomes empty
+                                if can_take && !state.is_rack_empty(&rack) && new_state.empty_rack(rack) {
+                                    new_state.carrying = 1;
+                                }
+                            } else if can_take {
+                                new_state.carrying = 1;
                             }
                         }
                         _ => { }
                     }
                 }
+                outcomes.push((new_state, 1.0 - motion.pickup_fail));
+                outcomes.push((*state, motion.pickup_fail));
             }
-            // place
-            4 => {
-                if state.carrying == 1 {
+            WarehouseAction::Place => {
+                let mut new_state = *state;
+                if state.carrying == 1 && !no_stopping {
                     match fwd_cell {
                         CellType::Rack => {
-                            new_carrying = 0;
-                            pack_position = (-1, -1);
-                            is_pack = 0;
+                            let rack = fwd_position.unwrap();
+                            if info.is_inventory_rack(&rack) {
+                                // a tracked slot must be empty to receive a pod
+                                if state.is_rack_empty(&rack) {
+                                    new_state.carrying = 0;
+                                    new_state.fill_rack(&rack);
+                                }
+                            } else {
+                                new_state.carrying = 0;
+                            }
                         }
                         CellType::Feed => {
-                            new_carrying = 0;
-                            pack_position = (-1, -1);
-                            is_pack = 0;
+                            new_state.carrying = 0;
                         }
                         CellType::Free => {
-                            new_carrying = 0;
-                            pack_position = fwd_position.unwrap();
-                            is_pack = 1;
+                            if new_state.add_pack(fwd_position.unwrap()) {
+                                new_state.carrying = 0;
+                            }
                         }
                         _ => { }
                     }
                 }
+                outcomes.push((new_state, 1.0 - motion.drop_fail));
+                outcomes.push((*state, motion.drop_fail));
             }
-            _ => {
-                // error
+        }
+        if let Some(battery) = &info.battery {
+            if info.charger_positions.contains(&state.agent_position) && a != WarehouseAction::Forward {
+                // the agent is charging rather than using charge
+                for (new_state, _) in outcomes.iter_mut() {
+                    new_state.battery = battery.charge(state.battery);
+                }
+            } else if state.battery < battery.consumption(a) {
+                // there is not enough charge to act
+                return Err(Error::ActionNotEnabled(action))
+            } else {
+                for (new_state, _) in outcomes.iter_mut() {
+                    new_state.battery = state.battery - battery.consumption(a);
+                }
             }
         }
-        let mut new_state: State = Default::default();
-        new_state.agent_position = new_agent_positions;
-        new_state.carrying = new_carrying;
-        new_state.agent_dir = new_dir;
-        new_state.pack_available = is_pack;
-        new_state.pack_position = pack_position;
-
-        let warehouse_word = WarehouseWord {
-            agent_position: new_agent_positions,
-            dir: new_dir,
-            carrying: new_carrying,
-            pack_position: if new_state.pack_available == 1 { Some(new_state.pack_position) } else { None }
-        };
-        Ok(vec![(new_state, 1.0, warehouse_word)])
-    }
-
-    fn warehouse_transition_map(&mut self, r: &f64, info: &Info) {
-        let states = self.get_states().to_vec();
-        let (_min_x, min_y) = info.rack_positions.iter().min().unwrap();
-        let (max_x, max_y) = info.rack_positions.iter().max().unwrap();
-        for state in states.iter() {
-            let state_idx = *self.get_state_mapping().get(state).unwrap() as i32;
-            for a in 0..self.action_space().end {
-                let sprimes = self.warehouse_step(state, a, info, max_x, min_y, max_y).unwrap();
-                let sprime_mapping: Vec<(i32, f64, WarehouseWord)> = sprimes
-                    .iter()
-                    .map(|(s, p, w)|
-                        (*self.get_state_mapping().get(s).unwrap() as i32, *p, w.clone())
-                    )
-                    .collect();
-                self.insert_transition(state_idx, a, sprime_mapping);
-                self.insert_reward(state_idx, a, *r)
+        // merge outcomes which lead to the same successor so that each successor appears once
+        let mut sprimes: Vec<(State, f64, WarehouseWord)> = Vec::new();
+        for (new_state, p) in outcomes.into_iter().filter(|(_, p)| *p > 0.) {
+            match sprimes.iter_mut().find(|(s, _, _)| *s == new_state) {
+                Some((_, q, _)) => { *q += p; }
+                None => { sprimes.push((new_state, p, WarehouseWord::from_state(&new_state, info))); }
             }
         }
+        Ok(sprimes)
+    }
+
+    fn reward(&self, rewards: &dyn RewardModel<State>, state: &State, a: i32, info: &Info) -> Vec<f64> {
+        rewards.reward(state, a, info)
+    }
+
+    fn scalarise(&self, rewards: &dyn RewardModel<State>, r: &[f64]) -> f64 {
+        rewards.scalarise(r)
     }
 }
 
@@ -510,7 +799,7 @@ pub fn create_decoded_sched_to_file(
     pi: &[f64],
     reverse_state_map: &HashMap<usize, (i32, i32)>,
     robot_reverse_state_map: &HashMap<usize, State>
-) -> std::collections::HashMap<String, std::collections::HashMap<String, Vec<TaskActionPair>>>{
+) -> Result<std::collections::HashMap<String, std::collections::HashMap<String, Vec<TaskActionPair>>>, Error> {
     // for each state in the policy
     //
     // get the product mdp state which will always take the form: (i32, i32)
@@ -522,23 +811,16 @@ pub fn create_decoded_sched_to_file(
     let mut sched_fn: std::collections::HashMap<String, std::collections::HashMap<String, Vec<TaskActionPair>>> =
         std::collections::HashMap::new();
     for sidx in 0..pi.len() {
-        let (s, q) = reverse_state_map.get(&sidx).unwrap();
-        let robot_state = robot_reverse_state_map.get(&(*s as usize)).unwrap();
+        let (s, q) = reverse_state_map.get(&sidx).ok_or_else(|| Error::state_not_in_mapping(&sidx))?;
+        let robot_state = robot_reverse_state_map.get(&(*s as usize))
+            .ok_or_else(|| Error::state_not_in_mapping(s))?;
         // write the robot state and the action taken at the robot state
         match sched_fn.get_mut(format!("{:?}", robot_state.agent_position).as_str()) {
             None => {
                 let mut new_dir_pair: std::collections::HashMap<String, Vec<TaskActionPair>> =
                     std::collections::HashMap::new();
                 new_dir_pair.insert(format!("{}", robot_state.agent_dir), vec![
-                    TaskActionPair {
-                        agent_dir: robot_state.agent_dir,
-                        agent_position: robot_state.agent_position,
-                        carrying: robot_state.carrying,
-                        pack_available: robot_state.pack_available,
-                        pack_position: robot_state.pack_position,
-                        action: pi[sidx] as i32,
-                        q: *q
-                    }
+                    TaskActionPair::new(robot_state, WarehouseAction::try_from(pi[sidx] as i32)?, *q)?
                 ]);
                 sched_fn.insert(format!("{:?}", robot_state.agent_position), new_dir_pair);
             }
@@ -546,35 +828,97 @@ pub fn create_decoded_sched_to_file(
                 match dirmap.get_mut(format!("{:?}", robot_state.agent_dir).as_str()) {
                     None => {
                         dirmap.insert(format!("{}", robot_state.agent_dir), vec![
-                            TaskActionPair {
-                                agent_dir: robot_state.agent_dir,
-                                agent_position: robot_state.agent_position,
-                                carrying: robot_state.carrying,
-                                pack_available: robot_state.pack_available,
-                                pack_position: robot_state.pack_position,
-                                action: pi[sidx] as i32,
-                                q: *q
-                            }
+                            TaskActionPair::new(robot_state, WarehouseAction::try_from(pi[sidx] as i32)?, *q)?
                         ]);
                     }
                     Some(v) => {
                         v.push(
-                            TaskActionPair {
-                                agent_dir: robot_state.agent_dir,
-                                agent_position: robot_state.agent_position,
-                                carrying: robot_state.carrying,
-                                pack_available: robot_state.pack_available,
-                                pack_position: robot_state.pack_position,
-                                action: pi[sidx] as i32,
-                                q: *q
-                            }
+                            TaskActionPair::new(robot_state, WarehouseAction::try_from(pi[sidx] as i32)?, *q)?
                         );
                     }
                 }
             }
         }
     }
-    sched_fn
+    Ok(sched_fn)
 }
 
-impl WarehouseEnv for Robot<State, WarehouseWord> { }
+#[cfg(test)]
+mod tests {
+    use crate::env::gym_env::build_model;
+    use crate::env::reward::ConstantReward;
+    use crate::env::warehouse::layout::WarehouseLayout;
+    use super::*;
+
+    #[test]
+    fn actions_without_charge_are_not_enabled() {
+        let layout = WarehouseLayout::from_ascii("C...\n.RR.\nF...").unwrap();
+        let (mut racks, mut corridors, mut rotation_mapping) = warehouse_defaults();
+        let mut info = layout.make_info(&mut racks, &mut corridors, &mut rotation_mapping);
+        info.battery = Some(BatteryModel { levels: 3, consumption: vec![0, 0, 1, 1, 1], charge_rate: 1, reserve: 0. });
+        let warehouse: Robot<State, WarehouseWord> = Robot::make(5, Default::default());
+        let mut state = info.initial_state((3, 0), 2);
+        state.battery = 0;
+        let forward = WarehouseAction::Forward.index();
+        assert!(matches!(warehouse.step(&state, forward, &info), Err(Error::ActionNotEnabled(2))));
+        // rotating uses no charge
+        let outcomes = warehouse.step(&state, WarehouseAction::RotateLeft.index(), &info).unwrap();
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].0.battery, 0);
+        // the disabled action is left out of the model rather than looping on the state
+        let warehouse = build_model::<Robot<State, WarehouseWord>>(5, Default::default(), &ConstantReward(1.), &info)
+            .unwrap();
+        let sidx = *warehouse.state_mapping.get(&state).unwrap() as i32;
+        assert!(!warehouse.transitions.contains_key(&(sidx, forward)));
+        assert!(warehouse.transitions.contains_key(&(sidx, WarehouseAction::RotateLeft.index())));
+        // on a charger the agent recharges instead
+        let charging = State { agent_position: (0, 0), ..state };
+        let outcomes = warehouse.step(&charging, WarehouseAction::RotateLeft.index(), &info).unwrap();
+        assert_eq!(outcomes[0].0.battery, 1);
+    }
+
+    #[test]
+    fn drift_follows_the_traffic_rules() {
+        let layout = WarehouseLayout::from_ascii("....\n....\n....").unwrap();
+        let (mut racks, mut corridors, mut rotation_mapping) = warehouse_defaults();
+        let mut info = layout.make_info(&mut racks, &mut corridors, &mut rotation_mapping);
+        info.motion_model.slip_drift = 0.2;
+        info.validate_model().unwrap();
+        let warehouse: Robot<State, WarehouseWord> = Robot::make(5, Default::default());
+        let state = info.initial_state((0, 1), 0);
+        let forward = WarehouseAction::Forward.index();
+        let positions = |outcomes: Vec<(State, f64, WarehouseWord)>| {
+            outcomes.into_iter().map(|(s, p, _)| (s.agent_position, p)).collect::<Vec<(Point, f64)>>()
+        };
+        assert_eq!(positions(warehouse.step(&state, forward, &info).unwrap()), vec![((1, 1), 0.8), ((1, 0), 0.1), ((1, 2), 0.1)]);
+        // the cell in front can only be left to the right, so drifting stops the agent
+        info.traffic.cell_headings.push(((1, 1), vec![0]));
+        assert_eq!(positions(warehouse.step(&state, forward, &info).unwrap()), vec![((1, 1), 0.8), ((0, 1), 0.2)]);
+    }
+
+    #[test]
+    fn front_pos_reports_unknown_headings() {
+        let layout = WarehouseLayout::from_ascii("...\n...").unwrap();
+        let (mut racks, mut corridors, mut rotation_mapping) = warehouse_defaults();
+        let info = layout.make_info(&mut racks, &mut corridors, &mut rotation_mapping);
+        assert_eq!(front_pos(&(1, 0), &1, info.rotation_mapping, info.width, info.height).unwrap(), Some((1, 1)));
+        assert_eq!(front_pos(&(1, 0), &3, info.rotation_mapping, info.width, info.height).unwrap(), None);
+        assert!(matches!(
+            front_pos(&(1, 0), &4, info.rotation_mapping, info.width, info.height),
+            Err(Error::UnknownHeading(4))
+        ));
+    }
+
+    #[test]
+    fn invalid_models_are_rejected_once() {
+        let layout = WarehouseLayout::from_ascii("....\n....").unwrap();
+        let (mut racks, mut corridors, mut rotation_mapping) = warehouse_defaults();
+        let mut info = layout.make_info(&mut racks, &mut corridors, &mut rotation_mapping);
+        info.motion_model.slip_stay = 0.7;
+        info.motion_model.slip_drift = 0.7;
+        assert!(matches!(info.validate_model(), Err(Error::InvalidModel(_))));
+        info.motion_model = Default::default();
+        info.traffic.cell_headings.push(((0, 0), vec![4]));
+        assert!(matches!(info.validate_model(), Err(Error::InvalidModel(_))));
+    }
+}
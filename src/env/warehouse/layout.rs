@@ -0,0 +1,437 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use hashbrown::{HashMap, HashSet};
+use serde::{Serialize, Deserialize};
+use crate::env::warehouse::high_fidelity_warehouse::{Info, MAX_PODS, Point};
+use crate::env::warehouse::traffic::{Highway, OneWayLane, TrafficRules};
+
+/*
+ASCII map legend, rows are read top to bottom (y = 0 is the first row) and columns left to right
+(x = 0 is the first column):
+.  free corridor cell
+R  rack
+I  rack whose occupancy is tracked (inventory rack), initially holding a pod
+E  inventory rack which is initially empty
+F  feed point
+Q  queue point (free cell)
+A  agent start position (free cell)
+C  charging station (free cell)
+#  obstacle, e.g. a pillar
+W  wall, doors are free cells left in a wall
+>  one-way lane heading right (free cell)
+v  one-way lane heading down (free cell)
+<  one-way lane heading left (free cell)
+^  one-way lane heading up (free cell)
+=  horizontal highway (free cell)
+|  vertical highway (free cell)
+X  no-stopping zone (free cell)
+*/
+
+#[derive(Debug)]
+pub enum LayoutError {
+    Io(std::io::Error),
+    Parse(String),
+    Invalid(String)
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::Io(e) => { write!(f, "unable to read layout: {}", e) }
+            LayoutError::Parse(e) => { write!(f, "unable to parse layout: {}", e) }
+            LayoutError::Invalid(e) => { write!(f, "invalid layout: {}", e) }
+        }
+    }
+}
+
+impl std::error::Error for LayoutError { }
+
+impl From<std::io::Error> for LayoutError {
+    fn from(e: std::io::Error) -> Self {
+        LayoutError::Io(e)
+    }
+}
+
+/// A declarative description of a warehouse site. A layout can be written as YAML or JSON with
+/// explicit point lists, as an ASCII map, or as YAML/JSON with an ASCII `map` whose points are
+/// appended to the explicit lists. The order of `queue_points` and `agent_starts` is significant:
+/// agent `i` starts at `agent_starts[i]` and regenerates to `queue_points[i]`. Points read from a
+/// map are ordered row by row.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WarehouseLayout {
+    #[serde(default)]
+    pub width: usize,
+    #[serde(default)]
+    pub height: usize,
+    #[serde(default)]
+    pub racks: Vec<Point>,
+    #[serde(default)]
+    pub feed_points: Vec<Point>,
+    #[serde(default)]
+    pub queue_points: Vec<Point>,
+    #[serde(default)]
+    pub agent_starts: Vec<Point>,
+    #[serde(default)]
+    pub chargers: Vec<Point>,
+    #[serde(default, alias = "blocked")]
+    pub obstacles: Vec<Point>,
+    #[serde(default)]
+    pub walls: Vec<Point>,
+    #[serde(default)]
+    pub one_way_lanes: Vec<OneWayLane>,
+    #[serde(default)]
+    pub highways: Vec<Highway>,
+    #[serde(default)]
+    pub no_stopping: Vec<Point>,
+    #[serde(default)]
+    pub cell_headings: Vec<(Point, Vec<u8>)>,
+    #[serde(default)]
+    pub inventory_racks: Vec<Point>, // racks whose occupancy is part of the state
+    #[serde(default)]
+    pub empty_racks: Vec<Point>, // inventory racks which start without a pod
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_pods_off_rack: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub map: Option<String>
+}
+
+impl WarehouseLayout {
+    /// Load a layout from disk, the format is chosen by the file extension: `.yaml`/`.yml`,
+    /// `.json`, and anything else is treated as an ASCII map
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<WarehouseLayout, LayoutError> {
+        let contents = fs::read_to_string(path.as_ref())?;
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => { WarehouseLayout::from_yaml_str(&contents) }
+            Some("json") => { WarehouseLayout::from_json_str(&contents) }
+            _ => { WarehouseLayout::from_ascii(&contents) }
+        }
+    }
+
+    pub fn from_yaml_str(s: &str) -> Result<WarehouseLayout, LayoutError> {
+        let layout: WarehouseLayout = serde_yaml::from_str(s)
+            .map_err(|e| LayoutError::Parse(e.to_string()))?;
+        layout.resolve()
+    }
+
+    pub fn from_json_str(s: &str) -> Result<WarehouseLayout, LayoutError> {
+        let layout: WarehouseLayout = serde_json::from_str(s)
+            .map_err(|e| LayoutError::Parse(e.to_string()))?;
+        layout.resolve()
+    }
+
+    pub fn from_ascii(s: &str) -> Result<WarehouseLayout, LayoutError> {
+        let layout = WarehouseLayout {
+            map: Some(s.to_string()),
+            ..Default::default()
+        };
+        layout.resolve()
+    }
+
+    /// Merge the ASCII map (if there is one) into the explicit point lists and validate the result
+    fn resolve(mut self) -> Result<WarehouseLayout, LayoutError> {
+        if let Some(map) = self.map.take() {
+            let lines: Vec<&str> = map.lines().map(|l| l.trim_end()).collect();
+            // blank lines are only trimmed from the ends, a blank row inside the map would shift
+            // every row after it
+            let first = lines.iter().position(|l| !l.is_empty());
+            let last = lines.iter().rposition(|l| !l.is_empty());
+            let (first, last) = match (first, last) {
+                (Some(first), Some(last)) => { (first, last) }
+                _ => { return Err(LayoutError::Parse("the map has no rows".to_string())) }
+            };
+            if let Some(blank) = (first..=last).find(|i| lines[*i].is_empty()) {
+                return Err(LayoutError::Parse(format!("line {} of the map is blank", blank + 1)))
+            }
+            let rows: Vec<&str> = lines[first..=last].to_vec();
+            let w = rows[0].chars().count();
+            if self.width == 0 && self.height == 0 {
+                self.width = w;
+                self.height = rows.len();
+            } else if self.width != w || self.height != rows.len() {
+                return Err(LayoutError::Invalid(format!(
+                    "the map is {}x{} but the layout declares {}x{}",
+                    w, rows.len(), self.width, self.height
+                )))
+            }
+            let mut lanes: Vec<OneWayLane> = (0..4u8)
+                .map(|dir| OneWayLane { cells: Vec::new(), dir })
+                .collect();
+            let mut highways: Vec<Highway> = [true, false].iter()
+                .map(|horizontal| Highway { cells: Vec::new(), horizontal: *horizontal })
+                .collect();
+            for (y, row) in rows.iter().enumerate() {
+                if row.chars().count() != w {
+                    return Err(LayoutError::Parse(format!(
+                        "row {} has {} columns, expected {}", y, row.chars().count(), w
+                    )))
+                }
+                for (x, c) in row.chars().enumerate() {
+                    let p: Point = (x as i32, y as i32);
+                    match c {
+                        '.' => { }
+                        'R' => { self.racks.push(p); }
+                        'I' => {
+                            self.racks.push(p);
+                            self.inventory_racks.push(p);
+                        }
+                        'E' => {
+                            self.racks.push(p);
+                            self.inventory_racks.push(p);
+                            self.empty_racks.push(p);
+                        }
+                        'F' => { self.feed_points.push(p); }
+                        'Q' => { self.queue_points.push(p); }
+                        'A' => { self.agent_starts.push(p); }
+                        'C' => { self.chargers.push(p); }
+                        '#' => { self.obstacles.push(p); }
+                        'W' => { self.walls.push(p); }
+                        '>' => { lanes[0].cells.push(p); }
+                        'v' => { lanes[1].cells.push(p); }
+                        '<' => { lanes[2].cells.push(p); }
+                        '^' => { lanes[3].cells.push(p); }
+                        '=' => { highways[0].cells.push(p); }
+                        '|' => { highways[1].cells.push(p); }
+                        'X' => { self.no_stopping.push(p); }
+                        _ => {
+                            return Err(LayoutError::Parse(format!(
+                                "unknown map symbol '{}' at {:?}", c, p
+                            )))
+                        }
+                    }
+                }
+            }
+            self.one_way_lanes.extend(lanes.into_iter().filter(|l| !l.cells.is_empty()));
+            self.highways.extend(highways.into_iter().filter(|h| !h.cells.is_empty()));
+        }
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Structural checks on the layout: dimensions, bounds and conflicting cell roles
+    pub fn validate(&self) -> Result<(), LayoutError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(LayoutError::Invalid("the layout must have a non-zero width and height".to_string()))
+        }
+        let in_bounds = |p: &Point| {
+            p.0 >= 0 && p.1 >= 0 && p.0 < self.width as i32 && p.1 < self.height as i32
+        };
+        let lane_cells = self.one_way_lanes.iter().flat_map(|l| l.cells.iter());
+        for (role, p) in self.racks.iter().map(|p| ("rack", p))
+            .chain(self.feed_points.iter().map(|p| ("feed point", p)))
+            .chain(self.queue_points.iter().map(|p| ("queue point", p)))
+            .chain(self.agent_starts.iter().map(|p| ("agent start", p)))
+            .chain(self.chargers.iter().map(|p| ("charger", p)))
+            .chain(self.obstacles.iter().map(|p| ("obstacle", p)))
+            .chain(self.walls.iter().map(|p| ("wall", p)))
+            .chain(lane_cells.map(|p| ("one-way lane", p)))
+            .chain(self.highways.iter().flat_map(|h| h.cells.iter()).map(|p| ("highway", p)))
+            .chain(self.no_stopping.iter().map(|p| ("no-stopping cell", p)))
+            .chain(self.cell_headings.iter().map(|(p, _)| ("cell heading", p))) {
+            if !in_bounds(p) {
+                return Err(LayoutError::Invalid(format!("{} {:?} is out of bounds", role, p)))
+            }
+        }
+        // racks, feed points, obstacles and walls are mutually exclusive and can not be stood on
+        let mut occupied: HashMap<Point, &'static str> = HashMap::new();
+        for (role, p) in self.racks.iter().map(|p| ("rack", p))
+            .chain(self.feed_points.iter().map(|p| ("feed point", p)))
+            .chain(self.obstacles.iter().map(|p| ("obstacle", p)))
+            .chain(self.walls.iter().map(|p| ("wall", p))) {
+            if let Some(other) = occupied.insert(*p, role) {
+                return Err(LayoutError::Invalid(format!(
+                    "{:?} is declared as both a {} and a {}", p, other, role
+                )))
+            }
+        }
+        for (role, p) in self.queue_points.iter().map(|p| ("queue point", p))
+            .chain(self.agent_starts.iter().map(|p| ("agent start", p)))
+            .chain(self.chargers.iter().map(|p| ("charger", p))) {
+            if let Some(other) = occupied.get(p) {
+                return Err(LayoutError::Invalid(format!(
+                    "{} {:?} is placed on a {}", role, p, other
+                )))
+            }
+        }
+        let mut starts: HashSet<Point> = HashSet::new();
+        for p in self.agent_starts.iter() {
+            if !starts.insert(*p) {
+                return Err(LayoutError::Invalid(format!("more than one agent starts at {:?}", p)))
+            }
+        }
+        let mut lanes: HashMap<Point, u8> = HashMap::new();
+        for lane in self.one_way_lanes.iter() {
+            if lane.dir > 3 {
+                return Err(LayoutError::Invalid(format!("one-way lane direction {} is not in 0..4", lane.dir)))
+            }
+            for p in lane.cells.iter() {
+                if let Some(other) = occupied.get(p) {
+                    return Err(LayoutError::Invalid(format!("one-way lane cell {:?} is placed on a {}", p, other)))
+                }
+                match lanes.insert(*p, lane.dir) {
+                    Some(d) if d != lane.dir => {
+                        return Err(LayoutError::Invalid(format!(
+                            "{:?} belongs to one-way lanes with directions {} and {}", p, d, lane.dir
+                        )))
+                    }
+                    _ => { }
+                }
+            }
+        }
+        for (p, headings) in self.cell_headings.iter() {
+            if headings.iter().any(|d| *d > 3) {
+                return Err(LayoutError::Invalid(format!("cell headings {:?} at {:?} are not in 0..4", headings, p)))
+            }
+        }
+        for p in self.inventory_racks.iter() {
+            if !self.racks.contains(p) {
+                return Err(LayoutError::Invalid(format!("inventory rack {:?} is not a rack", p)))
+            }
+        }
+        for p in self.empty_racks.iter() {
+            if !self.inventory_racks.contains(p) {
+                return Err(LayoutError::Invalid(format!("empty rack {:?} is not an inventory rack", p)))
+            }
+        }
+        if self.empty_racks.len() > MAX_PODS {
+            return Err(LayoutError::Invalid(format!(
+                "{} empty racks declared, at most {} can be empty", self.empty_racks.len(), MAX_PODS
+            )))
+        }
+        if let Some(m) = self.max_pods_off_rack {
+            if m == 0 || m > MAX_PODS {
+                return Err(LayoutError::Invalid(format!("max_pods_off_rack must be in 1..={}", MAX_PODS)))
+            }
+        }
+        Ok(())
+    }
+
+    /// Every cell that an agent may occupy: all grid cells that are not racks, feed points,
+    /// obstacles or walls
+    pub fn corridor_positions(&self) -> Vec<Point> {
+        let not_free: HashSet<Point> = self.racks.iter()
+            .chain(self.feed_points.iter())
+            .chain(self.obstacles.iter())
+            .chain(self.walls.iter())
+            .copied()
+            .collect();
+        let mut corridors: Vec<Point> = Vec::new();
+        for x in 0..self.width as i32 {
+            for y in 0..self.height as i32 {
+                if !not_free.contains(&(x, y)) {
+                    corridors.push((x, y));
+                }
+            }
+        }
+        corridors
+    }
+
+    pub fn traffic_rules(&self) -> TrafficRules {
+        TrafficRules {
+            cell_headings: self.cell_headings.to_vec(),
+            one_way_lanes: self.one_way_lanes.to_vec(),
+            highways: self.highways.to_vec(),
+            no_stopping: self.no_stopping.to_vec()
+        }
+    }
+
+    /// Populate an `Info` from the layout. The rack, corridor and rotation storage follows the
+    /// same convention as `Info::make` and is expected to be empty, e.g. from `warehouse_defaults`
+    pub fn make_info<'a>(
+        &'a self,
+        racks: &'a mut Vec<Point>,
+        corridors: &'a mut Vec<Point>,
+        rotation_mapping: &'a mut HashMap<u8, (i32, i32)>
+    ) -> Info<'a> {
+        let mut info = Info::make(
+            racks,
+            corridors,
+            rotation_mapping,
+            &self.feed_points[..],
+            self.width,
+            self.height
+        );
+        info.set_racks(Some(self.racks.to_vec()));
+        info.set_corridors(Some(self.corridor_positions()));
+        info.set_rotation_mapping();
+        if let Some(q) = self.queue_points.first() {
+            info.queue_point = *q;
+        }
+        info.queue_points = self.queue_points.to_vec();
+        info.agent_start_positions = self.agent_starts.to_vec();
+        info.charger_positions = self.chargers.to_vec();
+        info.obstacle_positions = self.obstacles.to_vec();
+        info.wall_positions = self.walls.to_vec();
+        info.set_traffic_rules(Some(self.traffic_rules()));
+        info.inventory_racks = self.inventory_racks.to_vec();
+        info.empty_rack_slots = self.empty_racks.to_vec();
+        if let Some(m) = self.max_pods_off_rack {
+            info.max_pods_off_rack = m;
+        }
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_an_ascii_map() {
+        let layout = WarehouseLayout::from_ascii("A..C\n.RE.\nF#>Q\n").unwrap();
+        assert_eq!((layout.width, layout.height), (4, 3));
+        assert_eq!(layout.agent_starts, vec![(0, 0)]);
+        assert_eq!(layout.chargers, vec![(3, 0)]);
+        assert_eq!(layout.racks, vec![(1, 1), (2, 1)]);
+        assert_eq!(layout.inventory_racks, vec![(2, 1)]);
+        assert_eq!(layout.empty_racks, vec![(2, 1)]);
+        assert_eq!(layout.feed_points, vec![(0, 2)]);
+        assert_eq!(layout.obstacles, vec![(1, 2)]);
+        assert_eq!(layout.queue_points, vec![(3, 2)]);
+        assert_eq!(layout.one_way_lanes, vec![OneWayLane { cells: vec![(2, 2)], dir: 0 }]);
+        assert!(layout.map.is_none());
+    }
+
+    #[test]
+    fn yaml_map_points_follow_the_explicit_lists() {
+        let layout = WarehouseLayout::from_yaml_str(
+            "queue_points: [[0, 0]]\nracks: [[3, 1]]\nmap: |\n  ...Q\n  .R..\n"
+        ).unwrap();
+        assert_eq!(layout.queue_points, vec![(0, 0), (3, 0)]);
+        assert_eq!(layout.racks, vec![(3, 1), (1, 1)]);
+        let explicit = WarehouseLayout::from_yaml_str("width: 3\nheight: 2\nblocked: [[1, 1]]").unwrap();
+        assert_eq!(explicit.obstacles, vec![(1, 1)]);
+        assert_eq!(explicit.corridor_positions().len(), 5);
+    }
+
+    #[test]
+    fn parses_the_12x12_layout() {
+        let layout = WarehouseLayout::from_yaml_str(include_str!("../../../layouts/warehouse_12x12.yaml")).unwrap();
+        assert_eq!((layout.width, layout.height), (12, 12));
+        assert_eq!(layout.racks.len(), 48);
+        assert_eq!(layout.feed_points, vec![(0, 5)]);
+        assert_eq!(layout.agent_starts.len(), 4);
+    }
+
+    #[test]
+    fn rejects_invalid_maps() {
+        assert!(matches!(WarehouseLayout::from_ascii(""), Err(LayoutError::Parse(_))));
+        assert!(matches!(WarehouseLayout::from_ascii("..\n..."), Err(LayoutError::Parse(_))));
+        assert!(matches!(WarehouseLayout::from_ascii("..\n.?"), Err(LayoutError::Parse(_))));
+        assert!(matches!(WarehouseLayout::from_ascii("..\n\n.."), Err(LayoutError::Parse(e)) if e.contains("line 2")));
+        assert_eq!(WarehouseLayout::from_ascii("\n..\nR.\n\n").unwrap().racks, vec![(0, 1)]);
+        assert!(matches!(
+            WarehouseLayout::from_yaml_str("width: 3\nheight: 3\nmap: |\n  ..\n  ..\n"),
+            Err(LayoutError::Invalid(_))
+        ));
+        assert!(matches!(
+            WarehouseLayout::from_yaml_str("map: \"..\"\nracks: [[5, 0]]"),
+            Err(LayoutError::Invalid(_))
+        ));
+        assert!(matches!(
+            WarehouseLayout::from_yaml_str("map: \"R.\"\nfeed_points: [[0, 0]]"),
+            Err(LayoutError::Invalid(_))
+        ));
+        assert!(matches!(WarehouseLayout::from_json_str("{\"width\": 2"), Err(LayoutError::Parse(_))));
+    }
+}
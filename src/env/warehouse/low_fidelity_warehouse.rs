@@ -3,8 +3,12 @@ use std::fs::OpenOptions;
 use itertools::iproduct;
 use scpm::agent::{Agent, MDPState, Robot};
 use scpm::scpm::definition::TaskAgentStateActionPair;
-use serde::Serialize;
-use crate::env::warehouse::high_fidelity_warehouse::{Info, Point};
+use serde::{Serialize, Deserialize};
+use crate::env::gym_env::Env;
+use crate::env::reward::RewardModel;
+use crate::error::Error;
+use crate::env::warehouse::action::LowResAction;
+use crate::env::warehouse::high_fidelity_warehouse::{Info, MotionModel, Point};
 
 pub type LowResState = (i32, i32);
 
@@ -33,18 +37,128 @@ impl Default for LowResWord {
     }
 }
 
-pub trait LowResEnv<T, S, W> where T: Agent<S, W>, W: Clone {
-    fn make(na: i32, init_state: S) -> T;
+/// Delays in the low fidelity warehouse. A move into a block is held up, leaving the agent where
+/// it is, with probability `1 - (1 - slip)(1 - c)` where `c` is the congestion of the low resolution
+/// block being entered (0 if the block is not listed)
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LowResNoise {
+    #[serde(default)]
+    pub slip: f64,
+    #[serde(default)]
+    pub congestion: Vec<(Point, f64)>
+}
+
+impl LowResNoise {
+    /// Derive the low fidelity slip from the high fidelity motion model. A forward move in the high
+    /// fidelity warehouse advances with probability p, so crossing a cell takes 1 / p attempts on
+    /// average; a low fidelity move which is held up with probability 1 - p has the same expected
+    /// number of attempts per cell, because a low fidelity move is costed as `grid_square` cells.
+    pub fn from_motion_model(motion: &MotionModel) -> LowResNoise {
+        LowResNoise {
+            slip: 1. - motion.forward_success(),
+            congestion: Vec::new()
+        }
+    }
+
+    pub fn set_congestion(&mut self, p: Point, c: f64) {
+        match self.congestion.iter_mut().find(|(q, _)| *q == p) {
+            Some((_, x)) => { *x = c; }
+            None => { self.congestion.push((p, c)); }
+        }
+    }
 
-    fn state_space(&mut self, w: &i32, h: &i32, grid_square: usize) -> (i32, i32);
+    pub fn delay_probability(&self, p: &Point) -> f64 {
+        let c = self.congestion.iter()
+            .find(|(q, _)| q == p)
+            .map(|(_, c)| *c)
+            .unwrap_or(0.);
+        1. - (1. - self.slip) * (1. - c)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (0. ..=1.).contains(&self.slip) && self.congestion.iter().all(|(_, c)| (0. ..=1.).contains(c))
+    }
+}
+
+/// The spatial abstraction between the high resolution warehouse grid and the low resolution grid.
+/// Each low resolution block covers `grid_square` x `grid_square` high resolution cells, blocks on
+/// the right and bottom edges are clipped to the warehouse when the dimensions do not divide evenly.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct GridAbstraction {
+    pub grid_square: usize,
+    // the high resolution warehouse dimensions
+    pub width: i32,
+    pub height: i32
+}
 
-    fn step(&mut self, state: &S, a: i32, w: &i32, h: &i32, info: &Info)
-        -> Result<Vec<(i32, f64, W)>, &'static str>;
+impl GridAbstraction {
+    pub fn new(width: i32, height: i32, grid_square: usize) -> GridAbstraction {
+        assert!(grid_square >= 1, "a low resolution block must contain at least one cell");
+        GridAbstraction {
+            grid_square,
+            width,
+            height
+        }
+    }
+
+    /// The dimensions of the low resolution grid
+    pub fn low_res_dims(&self) -> (i32, i32) {
+        let g = self.grid_square as i32;
+        ((self.width + g - 1) / g, (self.height + g - 1) / g)
+    }
+
+    /// The low resolution block containing a high resolution cell
+    pub fn to_low_res(&self, p: &Point) -> Point {
+        let g = self.grid_square as i32;
+        (p.0 / g, p.1 / g)
+    }
+
+    /// All of the high resolution cells in a low resolution block
+    pub fn block_cells(&self, block: &Point) -> Vec<Point> {
+        let g = self.grid_square as i32;
+        let (bx, by) = block;
+        let mut cells: Vec<Point> = Vec::new();
+        for x in bx * g..((bx + 1) * g).min(self.width) {
+            for y in by * g..((by + 1) * g).min(self.height) {
+                cells.push((x, y));
+            }
+        }
+        cells
+    }
+
+    /// The number of high resolution moves aggregated into one low resolution move
+    pub fn move_cost(&self) -> f64 {
+        self.grid_square as f64
+    }
+}
 
-    fn transition_map(&mut self, r: &f64, w: &i32, h: &i32, info: &Info);
+/// A low resolution move from `block` in direction `dir` is allowed if the high resolution layout
+/// has some cell on the edge of the block from which an agent may cross into the neighbouring block,
+/// i.e. neither cell is an obstacle or wall and the traffic rules allow heading `dir` from the edge cell.
+/// Racks do not block low resolution moves because tasks are located by the block containing the rack.
+fn block_move_allowed(info: &Info, block: &Point, dir: u8) -> bool {
+    let abstraction = &info.grid_abstraction;
+    let (dx, dy) = match info.rotation_mapping.get(&dir) {
+        Some(d) => *d,
+        None => { return false }
+    };
+    let target = (block.0 + dx, block.1 + dy);
+    abstraction.block_cells(block).iter().any(|c| {
+        let n: Point = (c.0 + dx, c.1 + dy);
+        n.0 >= 0 && n.1 >= 0 && n.0 < abstraction.width && n.1 < abstraction.height
+            && abstraction.to_low_res(&n) == target
+            && !info.is_blocked(c)
+            && !info.is_blocked(&n)
+            && info.traffic.allows(c, dir)
+    })
 }
 
-impl LowResEnv<Robot<LowResState, LowResWord>, LowResState, LowResWord> for Robot<LowResState, LowResWord> {
+impl Env for Robot<LowResState, LowResWord> {
+    type State = LowResState;
+    type Word = LowResWord;
+    type Context<'c> = Info<'c>;
+    type Rewards = dyn RewardModel<LowResState>;
+
     fn make(na: i32, init_state: LowResState) -> Robot<LowResState, LowResWord> {
         Robot {
             states: vec![],
@@ -60,16 +174,9 @@ impl LowResEnv<Robot<LowResState, LowResWord>, LowResState, LowResWord> for Robo
     }
 
     /// The state space is a low resolution version of the the full grid map representation of a
-    /// warehouse
-    /// w: is the original warehouse width
-    /// h: is the original warehouse height
-    /// grid_square is the low resolution mapping of original grid squares, for example 4 original
-    /// squares fit into 1 low res square -> grid_square = 4
-    ///
-    /// Returns the grid size of the new low res grid
-    fn state_space(&mut self, w: &i32, h: &i32, grid_square: usize) -> (i32, i32) {
-        let wnew = ((*w as f64 ) / (grid_square as f64)).ceil() as i32;
-        let hnew = ((*h as f64 ) / ( grid_square  as f64)).ceil() as i32;
+    /// warehouse, with one state per block of the grid abstraction of `info`
+    fn state_space(&mut self, info: &Info) {
+        let (wnew, hnew) = info.grid_abstraction.low_res_dims();
         for (ix, (x, y)) in iproduct!((0..wnew), (0..hnew)).enumerate() {
             self.set_state(&(x, y));
             self.insert_state_mapping(&(x, y), ix);
@@ -78,81 +185,43 @@ impl LowResEnv<Robot<LowResState, LowResWord>, LowResState, LowResWord> for Robo
             });
         }
         self.set_reverse_state_mapping();
-        (wnew, hnew)
     }
 
-    fn step(&mut self, state: &LowResState, a: i32, w: &i32, h: &i32, info: &Info)
-        -> Result<Vec<(i32, f64, LowResWord)>, &'static str> {
-
-        let (_min_x, min_y) = info.rack_positions.iter().min().unwrap();
-        let (max_x, max_y) = info.rack_positions.iter().max().unwrap();
-
-        let (px, py) = state;
-        // Left
-        let (xnew, ynew) = if a == 0 {
-            if *px > 0 {
-                (*px - 1, *py)
-            } else {
-                (*px, *py)
-            }
-        } else if a == 1 {
-            // Right
-            if *px < *w - 1 {
-                (*px + 1, *py)
-            } else {
-                (*px, *py)
-            }
-        } else if a == 2 {
-            // up
-            //if *px >= *min_x + 2 && *px <= *max_x - 2 && *py >= *min_y &&*py <= *max_y && *px % 2 != 0 {
-            if *px <= *max_x - 2 && *py >= *min_y &&*py <= *max_y && *px % 2 != 0 {
-                (*px, *py)
-            } else {
-                if *py < *h - 1 {
-                    (*px, *py + 1)
-                } else {
-                    (*px, *py)
-                }
-            }
-        } else if a == 3 {
-            // down
-            //if *px >= *min_x + 2 && *px <= *max_x - 2 && *py >= *min_y &&*py <= *max_y && *px % 2 != 0 {
-            if *px <= *max_x - 2 && *py >= *min_y &&*py <= *max_y && *px % 2 != 0 {
-                (*px, *py)
-            } else {
-                if *py > 0 {
-                    (*px, *py - 1)
-                } else {
-                    (*px, *py)
-                }
-            }
-        } else {
-            panic!("Action not found")
+    fn step(&self, state: &LowResState, a: i32, info: &Info)
+        -> Result<Vec<(LowResState, f64, LowResWord)>, Error> {
+        // the low resolution actions move one block along a heading of the rotation mapping
+        let dir: u8 = LowResAction::try_from(a)?.heading().index();
+        let snew: LowResState = match info.rotation_mapping.get(&dir) {
+            Some((dx, dy)) if block_move_allowed(info, state, dir) => { (state.0 + dx, state.1 + dy) }
+            _ => { *state }
         };
-        let snew: LowResState = (xnew, ynew);
-        let sidx = self.get_state_mapping().get(&snew).unwrap();
-        Ok(vec![(*sidx as i32, 1.0, LowResWord::new(&snew))])
-    }
-
-    fn transition_map(&mut self, r: &f64, w: &i32, h: &i32, info: &Info) {
-        let state_space = self.states.to_vec();
-        for s in state_space.iter() {
-            let sidx = *self.get_state_mapping().get(s).unwrap();
-            for a in self.actions.start..self.actions.end {
-                match self.step(s, a, w, h, info) {
-                    Ok(v) => {
-                        self.transitions.insert((sidx as i32, a), v);
-                        self.insert_reward(sidx as i32, a, *r);
-                    }
-                    Err(e) => {
-                        panic!("{:?}", e)
-                    }
-                }
-            }
+        if snew == *state {
+            return Ok(vec![(snew, 1.0, LowResWord::new(&snew))])
         }
+        // the move may be held up, in which case the agent remains where it is
+        let delay = info.low_res_noise.delay_probability(&snew);
+        let mut sprimes = Vec::new();
+        if delay < 1. {
+            sprimes.push((snew, 1.0 - delay, LowResWord::new(&snew)));
+        }
+        if delay > 0. {
+            sprimes.push((*state, delay, LowResWord::new(state)));
+        }
+        Ok(sprimes)
     }
-}
 
+    fn reward(&self, rewards: &dyn RewardModel<LowResState>, state: &LowResState, a: i32, info: &Info) -> Vec<f64> {
+        // a low resolution move stands in for grid_square high resolution moves
+        rewards.reward(state, a, info)
+            .into_iter()
+            .map(|r| r * info.grid_abstraction.move_cost())
+            .collect()
+    }
+
+    fn scalarise(&self, rewards: &dyn RewardModel<LowResState>, r: &[f64]) -> f64 {
+        rewards.scalarise(r)
+    }
+}
 
 #[derive(Serialize, Debug)]
 struct MapResult {
@@ -178,29 +247,36 @@ pub fn construct_agent_scheduler_hdd<'a, W> (
     mappings: &Mappings,
     data: &[&TaskAgentStateActionPair],
     state_mapping_fname: &str,
-    robot_initial_states: &[LowResState]) {
+    robot_initial_states: &[LowResState]) -> Result<(), Error> {
     let mut new_map: Vec<Mapping> = Vec::new();
     // load in the state mapping for the agent
-    let pth = format!("{}/schedulers", std::env::var("SCPM_HOME").unwrap());
+    let home = std::env::var("SCPM_HOME").map_err(|_| Error::MissingEnvVar("SCPM_HOME"))?;
+    let pth = format!("{}/schedulers", home);
     let filename = format!("{}/{}", pth, state_mapping_fname);
     for state_action in data.iter() {
-        let sq = mappings.current.get(&state_action.s).unwrap();
-        let sqprime = if state_action.sprime_a == state_action.s_a + 1 && state_action.sprime_t == state_action.s_t {
-            mappings.next_agent.as_ref().unwrap().get(&state_action.sprime).unwrap()
+        let sq = mappings.current.get(&state_action.s)
+            .ok_or_else(|| Error::state_not_in_mapping(&state_action.s))?;
+        let sprime_mapping = if state_action.sprime_a == state_action.s_a + 1 && state_action.sprime_t == state_action.s_t {
+            mappings.next_agent.as_ref()
         } else if state_action.sprime_a == 0 && state_action.sprime_t == state_action.s_t + 1 {
-            mappings.next_task.as_ref().unwrap().get(&state_action.sprime).unwrap()
+            mappings.next_task.as_ref()
         } else {
-            mappings.current.get(&state_action.sprime).unwrap()
+            Some(&mappings.current)
         };
+        let sqprime = sprime_mapping
+            .and_then(|m| m.get(&state_action.sprime))
+            .ok_or_else(|| Error::state_not_in_mapping(&state_action.sprime))?;
         //let sqprime = mdp.reverse_state_mapping.get(&state_action.sprime).unwrap();
         println!("agent: {}, task: {}, sidx: {}, s: {:?}, a: {} => sidx': {}, s': {:?}, a: {} t: {}",
                  state_action.s_a, state_action.s_t, state_action.s, sq, state_action.action, state_action.sprime,
                  sqprime, state_action.sprime_a, state_action.sprime_t);
-        let s = robot.reverse_state_mapping.get(&(sq.s as usize)).unwrap().clone();
+        let s = robot.reverse_state_mapping.get(&(sq.s as usize))
+            .ok_or_else(|| Error::state_not_in_mapping(&sq.s))?.clone();
         let sprime = if state_action.sprime_a != state_action.s_a || state_action.sprime_t != state_action.s_t {
             robot_initial_states[state_action.sprime_a].clone()
         } else {
-            robot.reverse_state_mapping.get(&(sqprime.s as usize)).unwrap().clone()
+            robot.reverse_state_mapping.get(&(sqprime.s as usize))
+                .ok_or_else(|| Error::state_not_in_mapping(&sqprime.s))?.clone()
         };
         new_map.push(
             Mapping {
@@ -216,9 +292,34 @@ pub fn construct_agent_scheduler_hdd<'a, W> (
         .create(true)
         .write(true)
         .truncate(true)
-        .open(filename).unwrap();
+        .open(filename)?;
     serde_json::to_writer_pretty(
         &file,
         &new_map
-    ).unwrap();
-}
\ No newline at end of file
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::warehouse::high_fidelity_warehouse::warehouse_defaults;
+    use crate::env::warehouse::layout::WarehouseLayout;
+    use super::*;
+
+    #[test]
+    fn congestion_delays_moves_and_is_validated_up_front() {
+        let layout = WarehouseLayout::from_ascii("....\n....").unwrap();
+        let (mut racks, mut corridors, mut rotation_mapping) = warehouse_defaults();
+        let mut info = layout.make_info(&mut racks, &mut corridors, &mut rotation_mapping);
+        info.low_res_noise.slip = 0.5;
+        info.low_res_noise.set_congestion((1, 0), 0.5);
+        info.validate_model().unwrap();
+        let warehouse: Robot<LowResState, LowResWord> = Robot::make(4, Default::default());
+        let right = LowResAction::Right.index();
+        let outcomes: Vec<(LowResState, f64)> = warehouse.step(&(0, 0), right, &info).unwrap()
+            .into_iter().map(|(s, p, _)| (s, p)).collect();
+        assert_eq!(outcomes, vec![((1, 0), 0.25), ((0, 0), 0.75)]);
+        info.low_res_noise.set_congestion((1, 0), 1.5);
+        assert!(matches!(info.validate_model(), Err(Error::InvalidModel(_))));
+    }
+}
@@ -1,2 +1,10 @@
+pub mod action;
+#[cfg(feature = "gif")]
+pub mod animation;
 pub mod high_fidelity_warehouse;
-pub mod low_fidelity_warehouse;
\ No newline at end of file
+pub mod low_fidelity_warehouse;
+pub mod layout;
+pub mod render;
+pub mod tasks;
+pub mod traffic;
+pub mod validation;
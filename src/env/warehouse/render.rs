@@ -0,0 +1,232 @@
+use std::fmt::Write;
+use crate::env::warehouse::action::Heading;
+use crate::env::warehouse::high_fidelity_warehouse::{CellType, Info, Point, State};
+
+/*
+The renderers draw the warehouse described by an `Info` with the symbols of the ASCII map legend in
+layout.rs, every cell is two characters wide:
+R  rack, I an inventory rack holding its pod, E an empty inventory rack slot
+F  feed point, Q queue point, C charging station, # obstacle, W wall
+>  v < ^ one-way lanes, = | highways, X no-stopping zone
+.  corridor, cells which are neither corridors nor any of the above are left blank
+p  pack dropped on the floor
+The second character of a cell is * if a trajectory passes through it. An agent is drawn as a letter,
+a for agent 0, b for agent 1 and so on, upper case when it carries a pod, followed by its heading.
+*/
+
+/// The agents, floor packs, rack occupancy and trajectories drawn over the layout. The packs and
+/// empty rack slots are those of any agent state, or the initially empty slots without agents.
+#[derive(Clone, Debug, Default)]
+pub struct Overlay {
+    pub agents: Vec<State>,
+    pub trajectories: Vec<Vec<Point>>
+}
+
+impl Overlay {
+    pub fn new(agents: Vec<State>) -> Overlay {
+        Overlay { agents, trajectories: Vec::new() }
+    }
+
+    pub fn with_trajectory(mut self, trajectory: &[State]) -> Overlay {
+        self.trajectories.push(trajectory.iter().map(|s| s.agent_position).collect());
+        self
+    }
+
+    /// The last state of each trajectory with the path it took, e.g. the `trajectories` of a
+    /// `SimulationReport` up to some step
+    pub fn from_trajectories(trajectories: &[Vec<State>]) -> Overlay {
+        Overlay {
+            agents: trajectories.iter().filter_map(|t| t.last().copied()).collect(),
+            trajectories: trajectories.iter()
+                .map(|t| t.iter().map(|s| s.agent_position).collect())
+                .collect()
+        }
+    }
+
+    fn packs(&self) -> Vec<Point> {
+        let mut packs: Vec<Point> = self.agents.iter().flat_map(|s| s.floor_packs()).collect();
+        packs.sort_unstable();
+        packs.dedup();
+        packs
+    }
+
+    fn empty_racks(&self, info: &Info) -> Vec<Point> {
+        if self.agents.is_empty() {
+            info.empty_rack_slots.to_vec()
+        } else {
+            self.agents.iter().flat_map(|s| s.empty_rack_slots()).collect()
+        }
+    }
+
+    fn on_trajectory(&self, p: &Point) -> bool {
+        self.trajectories.iter().any(|t| t.contains(p))
+    }
+}
+
+fn heading_symbol(h: Heading) -> char {
+    match h {
+        Heading::Right => { '>' }
+        Heading::Down => { 'v' }
+        Heading::Left => { '<' }
+        Heading::Up => { '^' }
+    }
+}
+
+fn agent_symbol(agent: usize, carrying: bool) -> char {
+    let c = (b'a' + (agent % 26) as u8) as char;
+    if carrying { c.to_ascii_uppercase() } else { c }
+}
+
+/// Fill colours of the SVG cells
+fn cell_colour(symbol: char) -> &'static str {
+    match symbol {
+        'R' | 'I' => { "#8d6e63" }
+        'E' => { "#d7ccc8" }
+        'F' => { "#66bb6a" }
+        'Q' => { "#4fc3f7" }
+        'C' => { "#ffd54f" }
+        '#' => { "#616161" }
+        'W' => { "#212121" }
+        'X' => { "#ef9a9a" }
+        'p' => { "#ffcc80" }
+        ' ' => { "#bdbdbd" }
+        _ => { "#fafafa" }
+    }
+}
+
+/// Stroke colours of the agents and trajectories
+const AGENT_COLOURS: [&str; 6] = ["#e53935", "#1e88e5", "#43a047", "#8e24aa", "#fb8c00", "#00897b"];
+
+/// The side of an SVG cell in pixels
+const SVG_CELL: i32 = 24;
+
+impl<'a> Info<'a> {
+    /// The legend symbol of the cell at `p`, before agents and trajectories are drawn
+    pub fn cell_symbol(&self, p: &Point, packs: &[Point], empty_racks: &[Point]) -> char {
+        match self.cell_type(p, packs) {
+            CellType::OutOfBounds => { ' ' }
+            CellType::Wall => { 'W' }
+            CellType::Obstacle => { '#' }
+            CellType::Rack if self.is_inventory_rack(p) => {
+                if empty_racks.contains(p) { 'E' } else { 'I' }
+            }
+            CellType::Rack => { 'R' }
+            CellType::Pack => { 'p' }
+            CellType::Feed => { 'F' }
+            CellType::Charger => { 'C' }
+            CellType::Queue => { 'Q' }
+            CellType::Free => {
+                if let Some(h) = self.traffic.highways.iter().find(|h| h.cells.contains(p)) {
+                    if h.horizontal { '=' } else { '|' }
+                } else if let Some(lane) = self.traffic.one_way_lanes.iter().find(|l| l.cells.contains(p)) {
+                    Heading::from_index(lane.dir).map_or('.', heading_symbol)
+                } else if self.traffic.is_no_stopping(p) {
+                    'X'
+                } else if self.corridor_positions.contains(p) {
+                    '.'
+                } else {
+                    ' '
+                }
+            }
+        }
+    }
+
+    /// Draw the warehouse with `overlay` as text, one line per row
+    pub fn render_ascii(&self, overlay: &Overlay) -> String {
+        let packs = overlay.packs();
+        let empty_racks = overlay.empty_racks(self);
+        let mut out = String::with_capacity((2 * self.width + 1) * self.height);
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                let p: Point = (x, y);
+                match overlay.agents.iter().position(|s| s.agent_position == p) {
+                    Some(i) => {
+                        let agent = &overlay.agents[i];
+                        out.push(agent_symbol(i, agent.carrying == 1));
+                        out.push(Heading::from_index(agent.agent_dir).map_or('?', heading_symbol));
+                    }
+                    None => {
+                        out.push(self.cell_symbol(&p, &packs[..], &empty_racks[..]));
+                        out.push(if overlay.on_trajectory(&p) { '*' } else { ' ' });
+                    }
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Draw the warehouse with `overlay` as an SVG document. Lanes and highways are labelled with
+    /// their symbol, trajectories are drawn as lines through the cell centres and agents as circles
+    /// with a line in their heading, filled when they carry a pod.
+    pub fn render_svg(&self, overlay: &Overlay) -> String {
+        let packs = overlay.packs();
+        let empty_racks = overlay.empty_racks(self);
+        let (w, h) = (self.width as i32 * SVG_CELL, self.height as i32 * SVG_CELL);
+        let centre = |p: &Point| (p.0 * SVG_CELL + SVG_CELL / 2, p.1 * SVG_CELL + SVG_CELL / 2);
+        let mut out = String::new();
+        // writing to a String can not fail
+        let _ = writeln!(
+            out,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" \
+             font-family=\"monospace\" font-size=\"{}\">",
+            w, h, w, h, SVG_CELL / 2
+        );
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                let p: Point = (x, y);
+                let symbol = self.cell_symbol(&p, &packs[..], &empty_racks[..]);
+                let _ = writeln!(
+                    out,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"#e0e0e0\"/>",
+                    x * SVG_CELL, y * SVG_CELL, SVG_CELL, SVG_CELL, cell_colour(symbol)
+                );
+                if matches!(symbol, '>' | 'v' | '<' | '^' | '=' | '|') {
+                    let (cx, cy) = centre(&p);
+                    let _ = writeln!(
+                        out,
+                        "<text x=\"{}\" y=\"{}\" fill=\"#9e9e9e\" text-anchor=\"middle\" \
+                         dominant-baseline=\"central\">{}</text>",
+                        cx, cy, escape(symbol)
+                    );
+                }
+            }
+        }
+        for (i, trajectory) in overlay.trajectories.iter().enumerate() {
+            let points: Vec<String> = trajectory.iter()
+                .map(|p| { let (cx, cy) = centre(p); format!("{},{}", cx, cy) })
+                .collect();
+            let _ = writeln!(
+                out,
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\" stroke-opacity=\"0.6\"/>",
+                points.join(" "), AGENT_COLOURS[i % AGENT_COLOURS.len()]
+            );
+        }
+        for (i, agent) in overlay.agents.iter().enumerate() {
+            let colour = AGENT_COLOURS[i % AGENT_COLOURS.len()];
+            let (cx, cy) = centre(&agent.agent_position);
+            let (dx, dy) = Heading::from_index(agent.agent_dir).map_or((0, 0), |h| h.offset());
+            let fill = if agent.carrying == 1 { colour } else { "#ffffff" };
+            let _ = writeln!(
+                out,
+                "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\" stroke=\"{}\" stroke-width=\"2\"/>",
+                cx, cy, SVG_CELL / 3, fill, colour
+            );
+            let _ = writeln!(
+                out,
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"3\"/>",
+                cx, cy, cx + dx * SVG_CELL / 2, cy + dy * SVG_CELL / 2, colour
+            );
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+}
+
+fn escape(c: char) -> String {
+    match c {
+        '<' => { "&lt;".to_string() }
+        '>' => { "&gt;".to_string() }
+        c => { c.to_string() }
+    }
+}
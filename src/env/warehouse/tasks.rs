@@ -0,0 +1,344 @@
+use scpm::dfa::definition::Data;
+use crate::env::gym_env::TaskProgress;
+use crate::env::warehouse::high_fidelity_warehouse::{front_pos, Info, WarehouseWord};
+use crate::env::warehouse::low_fidelity_warehouse::LowResWord;
+
+/*
+The task DFAs of the warehouse. Each guard is a function of the DFA state q, the word of the
+state entered and the warehouse Info, so the same transition function builds the products for the
+solvers (through the `Data` wrappers) and tracks q when a scheduler is executed.
+
+Low fidelity replenishment: 0 goto rack, 1 goto feed, 2 return to rack, 3 accept, 4 done
+High fidelity replenishment: 0 goto rack, 1 pickup, 2 carry to feed, 3 carry back, 4 drop,
+                             5 accept, 6 done, 7 fail
+Regeneration: 0 goto queue point, 1 accept, 2 done
+Recharge: 0 goto charger, 1 accept, 2 done, 3 fail (the battery dropped below the reserve)
+*/
+
+/// The shape of a high fidelity task DFA and its transition function
+#[derive(Clone, Copy)]
+pub struct TaskDfa {
+    pub init: i32,
+    pub num_states: i32,
+    pub accepting: &'static [i32],
+    pub rejecting: &'static [i32],
+    /// The accepting states and the states which can only be reached through them
+    pub complete: &'static [i32],
+    pub next: fn(i32, &WarehouseWord, &Info) -> i32
+}
+
+impl TaskDfa {
+    pub fn replenishment() -> TaskDfa {
+        TaskDfa {
+            init: 0,
+            num_states: 8,
+            accepting: &[5],
+            rejecting: &[7],
+            complete: &[5, 6],
+            next: hr_replenishment_next
+        }
+    }
+
+    pub fn regeneration() -> TaskDfa {
+        TaskDfa {
+            init: 0,
+            num_states: 3,
+            accepting: &[1],
+            rejecting: &[],
+            complete: &[1, 2],
+            next: regeneration_next
+        }
+    }
+
+    /// Reach a charger without the battery dropping below the reserve of the battery model
+    pub fn recharge() -> TaskDfa {
+        TaskDfa {
+            init: 0,
+            num_states: 4,
+            accepting: &[1],
+            rejecting: &[3],
+            complete: &[1, 2],
+            next: recharge_next
+        }
+    }
+
+    pub fn states(&self) -> Vec<i32> {
+        (0..self.num_states).collect()
+    }
+
+    pub fn is_complete(&self, q: i32) -> bool {
+        self.complete.contains(&q)
+    }
+
+    pub fn is_rejecting(&self, q: i32) -> bool {
+        self.rejecting.contains(&q)
+    }
+}
+
+/// A high fidelity task DFA with the `Info` its guards read the task parameters from
+pub struct WarehouseTask<'e, 'a> {
+    pub dfa: TaskDfa,
+    pub info: &'e Info<'a>
+}
+
+impl<'e, 'a> TaskProgress<WarehouseWord> for WarehouseTask<'e, 'a> {
+    fn init(&self) -> i32 {
+        self.dfa.init
+    }
+
+    fn next(&self, q: i32, w: &WarehouseWord) -> i32 {
+        (self.dfa.next)(q, w, self.info)
+    }
+
+    fn is_accepting(&self, q: i32) -> bool {
+        self.dfa.accepting.contains(&q)
+    }
+
+    fn is_done(&self, q: i32) -> bool {
+        self.dfa.is_complete(q) || self.dfa.is_rejecting(q)
+    }
+}
+
+fn goto_rack_position(data: &Data<LowResWord, &Info>, qprime: i32, q: i32) -> i32 {
+    let info_ref = data.info.as_ref().unwrap();
+    // the low resolution target is the block containing the rack
+    let rack = info_ref.grid_abstraction.to_low_res(&info_ref.rack_positions[info_ref.lookup_rack]);
+    if data.w.agent_position == rack {
+        return qprime
+    } else {
+        return q
+    }
+}
+
+fn goto_feed_position(data: &Data<LowResWord, &Info>) -> i32 {
+    let info_ref = data.info.as_ref().unwrap();
+    let feed = info_ref.grid_abstraction.to_low_res(&info_ref.feed_points[info_ref.feed_option]);
+    if data.w.agent_position == feed {
+        return 2
+    } else {
+        return 1
+    }
+}
+
+fn finish(q: i32) -> i32 {
+    q
+}
+
+fn done(q: i32) -> i32 {
+    q
+}
+
+pub fn lr_replenishment(data: &Data<LowResWord, &Info>) -> i32 {
+    let qprime = match data.q {
+        0 => {Ok(goto_rack_position(&data, 0, 1))}
+        1 => {Ok(goto_feed_position(&data))}
+        2 => {Ok(goto_rack_position(&data, 3, 2))}
+        3 => {Ok(finish(4))}
+        4 => {Ok(done(4))}
+        _ => {Err("Q state not found")}
+    };
+    match qprime {
+        Ok(i) => { i }
+        Err(_) => { -1 }
+    }
+}
+
+/// DFA transition telling the robot to goto some random rack position
+fn goto_rand_rackpos(w: &WarehouseWord, info: &Info) -> i32 {
+    // the guards can not fail, a word with an unknown heading faces no cell
+    let front = front_pos(
+        &w.agent_position,
+        &w.dir,
+        info.rotation_mapping,
+        info.width,
+        info.height
+    ).unwrap_or(None);
+    if *w.is_carrying() == 0 {
+        match front {
+            Some(point) => {
+                if point == info.rack_positions[info.lookup_rack] {
+                    return 1
+                }
+            }
+            None => { }
+        }
+    } else {
+        return 7
+    }
+    return 0
+}
+
+fn pickup_rack_at_pos(w: &WarehouseWord) -> i32 {
+    if *w.is_carrying() == 1 {
+        return 2
+    } else {
+        return 1
+    }
+}
+
+fn carry_rack_to_feed0(w: &WarehouseWord, info: &Info) -> i32 {
+    let front = front_pos(
+        &w.agent_position,
+        &w.dir,
+        info.rotation_mapping,
+        info.width,
+        info.height
+    ).unwrap_or(None);
+    if *w.is_carrying() == 1 {
+        match front {
+            Some(point) => {
+                if point == info.feed_points[info.feed_option] {
+                    return 3
+                }
+            }
+            None => { }
+        }
+    } else {
+        return 7
+    }
+    return 2
+}
+
+// return the rack back to its original position
+fn carry_rack_back_to_pos(w: &WarehouseWord, info: &Info) -> i32 {
+    let front = front_pos(
+        &w.agent_position,
+        &w.dir,
+        info.rotation_mapping,
+        info.width,
+        info.height
+    ).unwrap_or(None);
+    if *w.is_carrying() == 1 {
+        match front {
+            Some(point) => {
+                if point == info.rack_positions[info.lookup_rack] {
+                    return 4
+                }
+            }
+            None => { }
+        }
+    } else {
+        return 7
+    }
+    return 3
+}
+
+fn drop_rack(w: &WarehouseWord) -> i32 {
+    if *w.is_carrying() == 0 {
+        return 5
+    } else {
+        return 4
+    }
+}
+
+fn fail() -> i32 {
+    7
+}
+
+pub fn hr_replenishment(data: &Data<WarehouseWord, &Info>) -> i32 {
+    hr_replenishment_next(data.q, &data.w, data.info.as_ref().unwrap())
+}
+
+/// The replenishment DFA transition from `q` on reading `w`
+pub fn hr_replenishment_next(q: i32, w: &WarehouseWord, info: &Info) -> i32 {
+    //let qfail = 6;
+    let qprime = match q {
+        0 => {Ok(goto_rand_rackpos(w, info))}
+        1 => {Ok(pickup_rack_at_pos(w))}
+        2 => {Ok(carry_rack_to_feed0(w, info))}
+        3 => {Ok(carry_rack_back_to_pos(w, info))}
+        4 => {Ok(drop_rack(w))}
+        5 => {Ok(finish(6))}
+        6 => {Ok(done(6))}
+        7 => {Ok(fail())}
+        _ => {Err("Q state not found")}
+    };
+    match qprime {
+        Ok(i) =>  { i }
+        Err(_) => { -1 }
+    }
+}
+
+fn goto_queue_pos(w: &WarehouseWord, info: &Info) -> i32 {
+    if w.agent_position == info.queue_point {
+        return 1
+    }
+    return 0
+}
+
+fn finish_regen() -> i32 {
+    2
+}
+
+fn done_regen() -> i32 {
+    2
+}
+
+pub fn regeneration(data: &Data<WarehouseWord, &Info>) -> i32 {
+    regeneration_next(data.q, &data.w, data.info.as_ref().unwrap())
+}
+
+/// The regeneration DFA transition from `q` on reading `w`
+pub fn regeneration_next(q: i32, w: &WarehouseWord, info: &Info) -> i32 {
+    let qprime = match q {
+        0 => { Ok(goto_queue_pos(w, info)) }
+        1 => {Ok(finish_regen())}
+        2 => {Ok(done_regen())}
+        _ => { Err("Q state not found")}
+    };
+    match qprime {
+        Ok(i) => { i }
+        Err(_) => { -1 }
+    }
+}
+
+fn goto_charger(w: &WarehouseWord, info: &Info) -> i32 {
+    let reserve = info.battery.as_ref().map_or(0., |b| b.reserve);
+    if !w.battery_at_least(info, reserve) {
+        3
+    } else if w.at_charger {
+        1
+    } else {
+        0
+    }
+}
+
+pub fn recharge(data: &Data<WarehouseWord, &Info>) -> i32 {
+    recharge_next(data.q, &data.w, data.info.as_ref().unwrap())
+}
+
+/// The recharge DFA transition from `q` on reading `w`
+pub fn recharge_next(q: i32, w: &WarehouseWord, info: &Info) -> i32 {
+    match q {
+        0 => { goto_charger(w, info) }
+        1 | 2 => { 2 }
+        3 => { 3 }
+        _ => { -1 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::warehouse::high_fidelity_warehouse::{BatteryModel, State, warehouse_defaults};
+    use crate::env::warehouse::layout::WarehouseLayout;
+    use super::*;
+
+    #[test]
+    fn recharge_rejects_a_battery_below_the_reserve() {
+        let layout = WarehouseLayout::from_ascii("C...\n.RR.\nF...").unwrap();
+        let (mut racks, mut corridors, mut rotation_mapping) = warehouse_defaults();
+        let mut info = layout.make_info(&mut racks, &mut corridors, &mut rotation_mapping);
+        info.battery = Some(BatteryModel { levels: 5, consumption: vec![1; 5], charge_rate: 1, reserve: 50. });
+        let dfa = TaskDfa::recharge();
+        let next = |q: i32, s: &State| (dfa.next)(q, &WarehouseWord::from_state(s, &info), &info);
+        let state = info.initial_state((3, 0), 2);
+        assert_eq!(next(dfa.init, &state), 0);
+        // 25% is below the reserve
+        let low = State { battery: 1, ..state };
+        assert!(dfa.is_rejecting(next(0, &low)));
+        let charger = State { battery: 2, agent_position: (0, 0), ..state };
+        assert_eq!(next(0, &charger), 1);
+        assert!(dfa.accepting.contains(&1));
+        assert!(dfa.is_complete(next(1, &charger)));
+    }
+}
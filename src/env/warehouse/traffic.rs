@@ -0,0 +1,138 @@
+use serde::{Serialize, Deserialize};
+use crate::env::warehouse::high_fidelity_warehouse::Point;
+
+/*
+Traffic rules restrict the heading in which an agent may leave a cell, headings follow the rotation
+mapping {0: Right, 1: Down, 2: Left, 3: Up}. For a cell the first matching rule applies:
+1. cell_headings: the cell may only be left in the listed headings
+2. highways: the cell may be left in either heading along the highway, overriding any one-way lane
+   on the cell
+3. one_way_lanes: the cell may not be left against the lane, i.e. in heading (dir + 2) % 4
+4. otherwise the cell is unrestricted
+No-stopping zones are cells in which an agent may not stop to pick up or place a pod.
+*/
+
+/// A one-way lane: every cell in `cells` may only be traversed in direction `dir`, where `dir`
+/// follows the rotation mapping {0: Right, 1: Down, 2: Left, 3: Up}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OneWayLane {
+    pub cells: Vec<Point>,
+    pub dir: u8
+}
+
+/// A bidirectional highway: the cells may be traversed in both directions along the highway even
+/// if they are part of a one-way lane
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Highway {
+    pub cells: Vec<Point>,
+    pub horizontal: bool
+}
+
+impl Highway {
+    /// Is heading `dir` along the highway
+    pub fn along(&self, dir: u8) -> bool {
+        if self.horizontal { dir % 2 == 0 } else { dir % 2 == 1 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct TrafficRules {
+    #[serde(default)]
+    pub cell_headings: Vec<(Point, Vec<u8>)>,
+    #[serde(default)]
+    pub one_way_lanes: Vec<OneWayLane>,
+    #[serde(default)]
+    pub highways: Vec<Highway>,
+    #[serde(default)]
+    pub no_stopping: Vec<Point>
+}
+
+impl TrafficRules {
+    /// The aisle rule of the original warehouse: between the first and last rack rows, aisle
+    /// columns left of the last rack column are one-way, odd columns heading up and even columns
+    /// heading down
+    pub fn parity_aisles(rack_positions: &[Point]) -> TrafficRules {
+        let mut rules: TrafficRules = Default::default();
+        let (min_y, max_y) = match (
+            rack_positions.iter().map(|p| p.1).min(),
+            rack_positions.iter().map(|p| p.1).max()
+        ) {
+            (Some(min_y), Some(max_y)) => (min_y, max_y),
+            _ => { return rules }
+        };
+        let max_x = rack_positions.iter().max().unwrap().0;
+        for x in 0..=max_x - 2 {
+            rules.one_way_lanes.push(OneWayLane {
+                cells: (min_y..=max_y).map(|y| (x, y)).collect(),
+                dir: if x % 2 != 0 { 3 } else { 1 }
+            });
+        }
+        rules
+    }
+
+    /// Is an agent at `p` allowed to move in heading `dir`
+    pub fn allows(&self, p: &Point, dir: u8) -> bool {
+        if let Some((_, headings)) = self.cell_headings.iter().find(|(q, _)| q == p) {
+            return headings.contains(&dir)
+        }
+        if self.highways.iter().any(|h| h.cells.contains(p) && h.along(dir)) {
+            return true
+        }
+        !self.one_way_lanes.iter()
+            .any(|l| l.cells.contains(p) && (l.dir + 2) % 4 == dir)
+    }
+
+    pub fn is_no_stopping(&self, p: &Point) -> bool {
+        self.no_stopping.contains(p)
+    }
+
+    /// Every heading named by the rules must follow the rotation mapping
+    pub fn is_valid(&self) -> bool {
+        self.cell_headings.iter().all(|(_, h)| h.iter().all(|d| *d <= 3))
+            && self.one_way_lanes.iter().all(|l| l.dir <= 3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::warehouse::high_fidelity_warehouse::{Info, warehouse_defaults};
+    use crate::env::warehouse::layout::WarehouseLayout;
+    use super::*;
+
+    /// The aisle rule hard-coded in the original warehouse step
+    fn baseline_allows(p: &Point, dir: u8, max_x: i32, min_y: i32, max_y: i32) -> bool {
+        let (px, py) = *p;
+        if px <= max_x - 2 && py >= min_y && py <= max_y {
+            if px % 2 != 0 { dir != 1 } else { dir != 3 }
+        } else {
+            true
+        }
+    }
+
+    #[test]
+    fn parity_aisles_reproduce_the_baseline_rule() {
+        let (mut racks, mut corridors, mut rotation_mapping) = warehouse_defaults();
+        let feed_points = [(0, 5)];
+        let mut info = Info::make(&mut racks, &mut corridors, &mut rotation_mapping, &feed_points, 12, 12);
+        info.set_racks(None);
+        let (_, min_y) = *info.rack_positions.iter().min().unwrap();
+        let (max_x, max_y) = *info.rack_positions.iter().max().unwrap();
+        let rules = TrafficRules::parity_aisles(&info.rack_positions[..]);
+        for x in 0..12 {
+            for y in 0..12 {
+                for dir in 0..4u8 {
+                    let baseline = baseline_allows(&(x, y), dir, max_x, min_y, max_y);
+                    assert_eq!(rules.allows(&(x, y), dir), baseline, "({}, {}) heading {}", x, y, dir);
+                }
+            }
+        }
+        // the lanes of the shipped 12x12 layout are the same rule on every cell an agent can be in
+        let layout = WarehouseLayout::from_yaml_str(include_str!("../../../layouts/warehouse_12x12.yaml")).unwrap();
+        let layout_rules = layout.traffic_rules();
+        for p in layout.corridor_positions().iter() {
+            for dir in 0..4u8 {
+                assert_eq!(layout_rules.allows(p, dir), rules.allows(p, dir), "{:?} heading {}", p, dir);
+            }
+        }
+    }
+}
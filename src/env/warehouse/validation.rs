@@ -0,0 +1,281 @@
+use std::collections::VecDeque;
+use std::fmt;
+use hashbrown::{HashMap, HashSet};
+use crate::env::warehouse::action::Heading;
+use crate::env::warehouse::high_fidelity_warehouse::{Info, Point};
+use crate::error::Error;
+
+/// A problem found in the warehouse described by an `Info`
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayoutIssue {
+    OutOfBounds { role: &'static str, position: Point },
+    OverlappingRoles { position: Point, first: &'static str, second: &'static str },
+    /// A corridor which no agent can reach from an agent start or queue point
+    UnreachableCorridor(Point),
+    /// A feed point with no reachable corridor cell next to it, so it can not be faced
+    FeedNotAccessible(Point),
+    /// A rack with no reachable corridor cell next to it, so it can not be faced
+    RackNotAccessible(Point),
+    /// An agent start which is not a corridor cell, e.g. it is a rack
+    InvalidAgentStart { agent: usize, position: Point, cell: &'static str },
+    UnreachableQueuePoint(Point),
+    UnreachableCharger(Point)
+}
+
+impl fmt::Display for LayoutIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutIssue::OutOfBounds { role, position } => {
+                write!(f, "{} {:?} is out of bounds", role, position)
+            }
+            LayoutIssue::OverlappingRoles { position, first, second } => {
+                write!(f, "{:?} is both a {} and a {}", position, first, second)
+            }
+            LayoutIssue::UnreachableCorridor(p) => { write!(f, "corridor {:?} is unreachable", p) }
+            LayoutIssue::FeedNotAccessible(p) => {
+                write!(f, "feed point {:?} has no reachable adjacent corridor", p)
+            }
+            LayoutIssue::RackNotAccessible(p) => {
+                write!(f, "rack {:?} has no reachable adjacent corridor", p)
+            }
+            LayoutIssue::InvalidAgentStart { agent, position, cell } => {
+                write!(f, "agent {} starts at {:?} which is a {}", agent, position, cell)
+            }
+            LayoutIssue::UnreachableQueuePoint(p) => { write!(f, "queue point {:?} is unreachable", p) }
+            LayoutIssue::UnreachableCharger(p) => { write!(f, "charger {:?} is unreachable", p) }
+        }
+    }
+}
+
+/// Every issue found by `Info::validate`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationReport {
+    pub issues: Vec<LayoutIssue>
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "the warehouse layout has {} issue(s):", self.issues.len())?;
+        for issue in self.issues.iter() {
+            writeln!(f, "  - {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationReport { }
+
+impl<'a> Info<'a> {
+    /// Check the layout before any models are constructed: cell roles must be in bounds and must
+    /// not overlap, agents must start on corridors, and every corridor, queue point and charger
+    /// must be reachable, and every rack and feed point faceable, from the agent starts and queue
+    /// points under the traffic rules. Queue points must be reachable from the agent starts alone.
+    /// If there are no agent starts or queue points only the adjacency of racks and feed points to
+    /// corridors is checked.
+    pub fn validate(&self) -> Result<(), ValidationReport> {
+        let mut issues: Vec<LayoutIssue> = Vec::new();
+        let in_bounds = |p: &Point| {
+            p.0 >= 0 && p.1 >= 0 && p.0 < self.width as i32 && p.1 < self.height as i32
+        };
+        let roles: Vec<(&'static str, &Point)> = self.rack_positions.iter().map(|p| ("rack", p))
+            .chain(self.feed_points.iter().map(|p| ("feed point", p)))
+            .chain(self.obstacle_positions.iter().map(|p| ("obstacle", p)))
+            .chain(self.wall_positions.iter().map(|p| ("wall", p)))
+            .chain(self.charger_positions.iter().map(|p| ("charger", p)))
+            .chain(self.queue_points.iter().map(|p| ("queue point", p)))
+            .chain(self.agent_start_positions.iter().map(|p| ("agent start", p)))
+            .chain(self.corridor_positions.iter().map(|p| ("corridor", p)))
+            .collect();
+        for (role, p) in roles.iter() {
+            if !in_bounds(p) {
+                issues.push(LayoutIssue::OutOfBounds { role, position: **p });
+            }
+        }
+        // racks, feed points, obstacles and walls can not be occupied and are exclusive, and no
+        // other role may be placed on them
+        let mut occupied: HashMap<Point, &'static str> = HashMap::new();
+        for (role, p) in roles.iter().take(
+            self.rack_positions.len() + self.feed_points.len()
+                + self.obstacle_positions.len() + self.wall_positions.len()
+        ) {
+            if let Some(first) = occupied.insert(**p, role) {
+                if first != *role {
+                    issues.push(LayoutIssue::OverlappingRoles { position: **p, first, second: role });
+                }
+            }
+        }
+        for (role, p) in roles.iter().filter(|(r, _)| *r == "charger" || *r == "queue point" || *r == "corridor") {
+            if let Some(first) = occupied.get(*p) {
+                issues.push(LayoutIssue::OverlappingRoles { position: **p, first, second: role });
+            }
+        }
+        let corridors: HashSet<Point> = self.corridor_positions.iter()
+            .filter(|p| in_bounds(p) && !occupied.contains_key(*p))
+            .copied()
+            .collect();
+        for (agent, p) in self.agent_start_positions.iter().enumerate() {
+            if !corridors.contains(p) {
+                let cell = occupied.get(p).copied().unwrap_or("cell outside the corridors");
+                issues.push(LayoutIssue::InvalidAgentStart { agent, position: *p, cell });
+            }
+        }
+        let seeds: Vec<Point> = self.agent_start_positions.iter()
+            .chain(self.queue_points.iter())
+            .filter(|p| corridors.contains(*p))
+            .copied()
+            .collect();
+        let reachable = if seeds.is_empty() { corridors.clone() } else { self.reachable(&seeds, &corridors) };
+        let mut unreachable: Vec<Point> = corridors.iter().filter(|p| !reachable.contains(*p)).copied().collect();
+        unreachable.sort();
+        issues.extend(unreachable.into_iter().map(LayoutIssue::UnreachableCorridor));
+        // every queue point seeds the search above, so check them against the agent starts
+        let starts: Vec<Point> = self.agent_start_positions.iter()
+            .filter(|p| corridors.contains(*p))
+            .copied()
+            .collect();
+        let from_starts = if starts.is_empty() { reachable.clone() } else { self.reachable(&starts, &corridors) };
+        for p in self.queue_points.iter() {
+            if corridors.contains(p) && !from_starts.contains(p) {
+                issues.push(LayoutIssue::UnreachableQueuePoint(*p));
+            }
+        }
+        for p in self.charger_positions.iter() {
+            if corridors.contains(p) && !reachable.contains(p) {
+                issues.push(LayoutIssue::UnreachableCharger(*p));
+            }
+        }
+        // a rack or feed point is used by facing it from an adjacent corridor
+        let faceable = |p: &Point| {
+            self.rotation_mapping.values().any(|(dx, dy)| reachable.contains(&(p.0 - dx, p.1 - dy)))
+        };
+        for p in self.feed_points.iter() {
+            if !faceable(p) {
+                issues.push(LayoutIssue::FeedNotAccessible(*p));
+            }
+        }
+        for p in self.rack_positions.iter() {
+            if !faceable(p) {
+                issues.push(LayoutIssue::RackNotAccessible(*p));
+            }
+        }
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationReport { issues })
+        }
+    }
+
+    /// Check the parameters of the transition models once, before they are stepped: the motion
+    /// model and low resolution noise probabilities, the headings of the traffic rules and the
+    /// rotation mapping. The `step` of each warehouse relies on these and does not check them again.
+    pub fn validate_model(&self) -> Result<(), Error> {
+        if !self.motion_model.is_valid() {
+            return Err(Error::InvalidModel(
+                "motion model probabilities must be in [0, 1] and forward slip must not exceed 1".to_string()
+            ))
+        }
+        if !self.low_res_noise.is_valid() {
+            return Err(Error::InvalidModel("low resolution noise probabilities must be in [0, 1]".to_string()))
+        }
+        if !self.traffic.is_valid() {
+            return Err(Error::InvalidModel("traffic rule headings must be in 0..4".to_string()))
+        }
+        if Heading::ALL.iter().any(|h| !self.rotation_mapping.contains_key(&h.index())) {
+            return Err(Error::InvalidModel("the rotation mapping must contain every heading".to_string()))
+        }
+        Ok(())
+    }
+
+    /// The corridor cells an agent can drive to from `seeds`, moving between adjacent corridors
+    /// in the headings allowed by the traffic rules
+    fn reachable(&self, seeds: &[Point], corridors: &HashSet<Point>) -> HashSet<Point> {
+        let mut visited: HashSet<Point> = seeds.iter().copied().collect();
+        let mut queue: VecDeque<Point> = seeds.iter().copied().collect();
+        while let Some(p) = queue.pop_front() {
+            for (dir, (dx, dy)) in self.rotation_mapping.iter() {
+                let n = (p.0 + dx, p.1 + dy);
+                if corridors.contains(&n) && self.traffic.allows(&p, *dir) && visited.insert(n) {
+                    queue.push_back(n);
+                }
+            }
+        }
+        visited
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::warehouse::high_fidelity_warehouse::warehouse_defaults;
+    use crate::env::warehouse::layout::WarehouseLayout;
+
+    fn issues(map: &str, edit: impl FnOnce(&mut Info)) -> Vec<LayoutIssue> {
+        let layout = WarehouseLayout::from_ascii(map).unwrap();
+        let (mut racks, mut corridors, mut rotation_mapping) = warehouse_defaults();
+        let mut info = layout.make_info(&mut racks, &mut corridors, &mut rotation_mapping);
+        edit(&mut info);
+        info.validate().err().map(|r| r.issues).unwrap_or_default()
+    }
+
+    #[test]
+    fn accepts_a_connected_layout() {
+        assert_eq!(issues("A.RF\n..Q.", |_| ()), vec![]);
+    }
+
+    #[test]
+    fn finds_out_of_bounds_roles() {
+        assert_eq!(
+            issues("A.", |info| info.charger_positions.push((5, 0))),
+            vec![LayoutIssue::OutOfBounds { role: "charger", position: (5, 0) }]
+        );
+    }
+
+    #[test]
+    fn finds_overlapping_roles() {
+        assert_eq!(
+            issues("A.R", |info| info.charger_positions.push((2, 0))),
+            vec![LayoutIssue::OverlappingRoles { position: (2, 0), first: "rack", second: "charger" }]
+        );
+    }
+
+    #[test]
+    fn finds_agents_starting_off_the_corridors() {
+        assert_eq!(
+            issues("..R", |info| info.agent_start_positions = vec![(2, 0)]),
+            vec![LayoutIssue::InvalidAgentStart { agent: 0, position: (2, 0), cell: "rack" }]
+        );
+    }
+
+    #[test]
+    fn finds_unreachable_corridors() {
+        assert_eq!(issues("A#.", |_| ()), vec![LayoutIssue::UnreachableCorridor((2, 0))]);
+    }
+
+    #[test]
+    fn finds_queue_points_unreachable_from_the_agent_starts() {
+        // the lane only leads away from the queue point
+        assert_eq!(issues("Q>A", |_| ()), vec![LayoutIssue::UnreachableQueuePoint((0, 0))]);
+    }
+
+    #[test]
+    fn finds_unreachable_chargers() {
+        assert_eq!(
+            issues("A#C", |_| ()),
+            vec![LayoutIssue::UnreachableCorridor((2, 0)), LayoutIssue::UnreachableCharger((2, 0))]
+        );
+    }
+
+    #[test]
+    fn finds_feed_points_which_can_not_be_faced() {
+        // the corridor next to the feed point is behind a lane leading away from it
+        assert_eq!(
+            issues("F.>A", |_| ()),
+            vec![LayoutIssue::UnreachableCorridor((1, 0)), LayoutIssue::FeedNotAccessible((0, 0))]
+        );
+    }
+
+    #[test]
+    fn finds_racks_which_can_not_be_faced() {
+        assert_eq!(issues("A.#R\n..##", |_| ()), vec![LayoutIssue::RackNotAccessible((3, 0))]);
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt;
+use crate::env::warehouse::layout::LayoutError;
+use crate::env::warehouse::validation::ValidationReport;
+
+/// The errors of the environments, the scheduler writers and the pipeline. Nothing in the library
+/// panics on these, they are returned to the caller.
+#[derive(Debug)]
+pub enum Error {
+    /// An action outside the action space of the environment
+    UnknownAction(i32),
+    /// An action of the action space which can not be taken in the state it was taken in
+    ActionNotEnabled(i32),
+    /// A heading outside 0..4
+    UnknownHeading(u8),
+    /// A state, formatted with `Debug`, which is not in the state mapping of the model
+    StateNotInMapping(String),
+    /// The layout, or a model built from it, is inconsistent
+    InvalidLayout(String),
+    /// A parameter of a transition model is out of range, e.g. a probability outside [0, 1]
+    InvalidModel(String),
+    /// The outcomes of a step do not form a distribution which can be sampled, e.g. there are none
+    InvalidDistribution(String),
+    /// The warehouse can not be drawn as asked, e.g. an animation too large for a GIF
+    Render(String),
+    /// A required environment variable is not set
+    MissingEnvVar(&'static str),
+    Io(std::io::Error),
+    /// A JSON document, e.g. a scheduler in the debug format, could not be parsed or written
+    Json(serde_json::Error),
+    /// An episodic environment was stepped after its episode finished, without a reset
+    EpisodeFinished
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownAction(a) => { write!(f, "action {} not found", a) }
+            Error::ActionNotEnabled(a) => { write!(f, "action {} is not enabled in this state", a) }
+            Error::UnknownHeading(d) => { write!(f, "heading {} not found", d) }
+            Error::StateNotInMapping(s) => { write!(f, "state {} is not in the state mapping", s) }
+            Error::InvalidLayout(e) => { write!(f, "invalid layout: {}", e) }
+            Error::InvalidModel(e) => { write!(f, "invalid model: {}", e) }
+            Error::InvalidDistribution(e) => { write!(f, "invalid transition distribution: {}", e) }
+            Error::Render(e) => { write!(f, "unable to render: {}", e) }
+            Error::MissingEnvVar(v) => { write!(f, "the {} environment variable is not set", v) }
+            Error::Io(e) => { write!(f, "{}", e) }
+            Error::Json(e) => { write!(f, "{}", e) }
+            Error::EpisodeFinished => { write!(f, "the episode has finished, reset the environment to start another") }
+        }
+    }
+}
+
+impl std::error::Error for Error { }
+
+impl Error {
+    pub fn state_not_in_mapping<S: fmt::Debug>(s: &S) -> Error {
+        Error::StateNotInMapping(format!("{:?}", s))
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+#[cfg(feature = "gif")]
+impl From<gif::EncodingError> for Error {
+    fn from(e: gif::EncodingError) -> Self {
+        match e {
+            gif::EncodingError::Io(e) => { Error::Io(e) }
+            e => { Error::Io(std::io::Error::other(e)) }
+        }
+    }
+}
+
+impl From<LayoutError> for Error {
+    fn from(e: LayoutError) -> Self {
+        match e {
+            LayoutError::Io(e) => { Error::Io(e) }
+            LayoutError::Parse(e) | LayoutError::Invalid(e) => { Error::InvalidLayout(e) }
+        }
+    }
+}
+
+impl From<ValidationReport> for Error {
+    fn from(report: ValidationReport) -> Self {
+        Error::InvalidLayout(report.to_string())
+    }
+}
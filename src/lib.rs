@@ -1 +1,7 @@
-pub mod env;
\ No newline at end of file
+pub mod artifacts;
+pub mod env;
+pub mod error;
+pub mod scenario;
+pub mod scheduler;
+#[cfg(feature = "python")]
+pub mod python;
@@ -0,0 +1,308 @@
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+use scpm::agent::Robot;
+use crate::env::gym_env::Env;
+use crate::env::warehouse::action::{Heading, WarehouseAction};
+use crate::env::warehouse::high_fidelity_warehouse::{Info, Point, State, warehouse_defaults, WarehouseWord};
+use crate::env::warehouse::layout::WarehouseLayout;
+use crate::env::warehouse::low_fidelity_warehouse::{LowResNoise, LowResState, LowResWord};
+use crate::env::warehouse::render::Overlay;
+use crate::env::warehouse::tasks::TaskDfa;
+use crate::error::Error;
+use crate::scheduler::policy::Policy;
+
+/*
+The `rusty_robots` Python extension module, built with maturin and the python feature. Every call
+builds the `Info` of the layout the same way as the pipeline does, so a Python wrapper steps the
+transition model the schedulers were synthesised against. States cross the boundary as tuples
+    (x, y, heading, carrying, packs, empty_racks, battery)
+where packs and empty_racks are tuples of (x, y) points, which keeps them hashable. Actions and
+headings are the integers of `ACTIONS` and `HEADINGS`.
+*/
+
+type StateTuple = (i32, i32, u8, u8, Vec<Point>, Vec<Point>, u8);
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => { PyIOError::new_err(e.to_string()) }
+            e => { PyValueError::new_err(e.to_string()) }
+        }
+    }
+}
+
+fn state_from_py(t: StateTuple) -> PyResult<State> {
+    let (x, y, agent_dir, carrying, packs, empty_racks, battery) = t;
+    let mut state = State { agent_dir, agent_position: (x, y), carrying, battery, ..Default::default() };
+    for p in packs.iter() {
+        if !state.add_pack(*p) {
+            return Err(PyValueError::new_err(format!("too many packs on the floor: {:?}", packs)))
+        }
+    }
+    for p in empty_racks.iter() {
+        if !state.empty_rack(*p) {
+            return Err(PyValueError::new_err(format!("too many empty rack slots: {:?}", empty_racks)))
+        }
+    }
+    Ok(state)
+}
+
+fn state_to_py(py: Python, s: &State) -> PyObject {
+    let packs = PyTuple::new(py, s.floor_packs().collect::<Vec<Point>>());
+    let empty_racks = PyTuple::new(py, s.empty_rack_slots().collect::<Vec<Point>>());
+    (s.agent_position.0, s.agent_position.1, s.agent_dir, s.carrying, packs, empty_racks, s.battery).into_py(py)
+}
+
+/// A warehouse layout with the task parameters of its `Info`
+#[pyclass(name = "Warehouse")]
+pub struct PyWarehouse {
+    layout: WarehouseLayout,
+    /// The rack of the replenishment task, an index into `racks`
+    #[pyo3(get, set)]
+    lookup_rack: usize,
+    /// The feed point of the replenishment task, an index into `feed_points`
+    #[pyo3(get, set)]
+    feed_option: usize,
+    /// The side of a low fidelity block in cells
+    #[pyo3(get, set)]
+    grid_square: usize
+}
+
+impl PyWarehouse {
+    fn new(layout: WarehouseLayout) -> PyWarehouse {
+        PyWarehouse { layout, lookup_rack: 0, feed_option: 0, grid_square: 1 }
+    }
+
+    fn with_info<T>(&self, f: impl FnOnce(&Info) -> Result<T, Error>) -> PyResult<T> {
+        if self.grid_square == 0 {
+            return Err(PyValueError::new_err("grid_square must be at least 1"))
+        }
+        // the task guards index the racks and feed points with these
+        if self.lookup_rack >= self.layout.racks.len() {
+            return Err(PyValueError::new_err(format!(
+                "lookup_rack {} is not one of the {} racks of the layout", self.lookup_rack, self.layout.racks.len()
+            )))
+        }
+        if self.feed_option >= self.layout.feed_points.len() {
+            return Err(PyValueError::new_err(format!(
+                "feed_option {} is not one of the {} feed points of the layout",
+                self.feed_option, self.layout.feed_points.len()
+            )))
+        }
+        let (mut racks, mut corridors, mut rotation_mapping) = warehouse_defaults();
+        let mut info = self.layout.make_info(&mut racks, &mut corridors, &mut rotation_mapping);
+        info.set_lookup_rack(self.lookup_rack);
+        info.feed_option = self.feed_option;
+        info.set_grid_square(self.grid_square);
+        info.low_res_noise = LowResNoise::from_motion_model(&info.motion_model);
+        info.validate_model()?;
+        Ok(f(&info)?)
+    }
+}
+
+#[pymethods]
+impl PyWarehouse {
+    /// Load a layout from a YAML, JSON or ASCII map file
+    #[staticmethod]
+    fn from_file(path: &str) -> PyResult<PyWarehouse> {
+        Ok(PyWarehouse::new(WarehouseLayout::from_file(path).map_err(Error::from)?))
+    }
+
+    #[staticmethod]
+    fn from_ascii(map: &str) -> PyResult<PyWarehouse> {
+        Ok(PyWarehouse::new(WarehouseLayout::from_ascii(map).map_err(Error::from)?))
+    }
+
+    #[getter]
+    fn width(&self) -> usize {
+        self.layout.width
+    }
+
+    #[getter]
+    fn height(&self) -> usize {
+        self.layout.height
+    }
+
+    #[getter]
+    fn racks(&self) -> Vec<Point> {
+        self.layout.racks.to_vec()
+    }
+
+    #[getter]
+    fn feed_points(&self) -> Vec<Point> {
+        self.layout.feed_points.to_vec()
+    }
+
+    #[getter]
+    fn queue_points(&self) -> Vec<Point> {
+        self.layout.queue_points.to_vec()
+    }
+
+    #[getter]
+    fn agent_starts(&self) -> Vec<Point> {
+        self.layout.agent_starts.to_vec()
+    }
+
+    /// The state of an agent at `position` facing `heading` at the start of a run
+    #[pyo3(signature = (position, heading = 0))]
+    fn initial_state(&self, py: Python, position: Point, heading: u8) -> PyResult<PyObject> {
+        Heading::try_from(heading)?;
+        let state = self.with_info(|info| Ok(info.initial_state(position, heading)))?;
+        Ok(state_to_py(py, &state))
+    }
+
+    /// The outcomes of taking `action` in `state` as a list of (state, probability), the
+    /// intended outcome first
+    fn step(&self, py: Python, state: StateTuple, action: i32) -> PyResult<Vec<(PyObject, f64)>> {
+        let state = state_from_py(state)?;
+        let outcomes = self.with_info(|info| {
+            let warehouse: Robot<State, WarehouseWord> = Robot::make(5, Default::default());
+            warehouse.step(&state, action, info)
+        })?;
+        Ok(outcomes.iter().map(|(s, p, _)| (state_to_py(py, s), *p)).collect())
+    }
+
+    /// Every state of the high fidelity model
+    fn state_space(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        let states = self.with_info(|info| {
+            let mut warehouse: Robot<State, WarehouseWord> = Robot::make(5, Default::default());
+            warehouse.state_space(info);
+            Ok(warehouse.states)
+        })?;
+        Ok(states.iter().map(|s| state_to_py(py, s)).collect())
+    }
+
+    /// The blocks of the low fidelity model, row by row within each column
+    fn low_res_state_space(&self) -> PyResult<Vec<LowResState>> {
+        self.with_info(|info| {
+            let mut warehouse: Robot<LowResState, LowResWord> = Robot::make(4, Default::default());
+            warehouse.state_space(info);
+            Ok(warehouse.states)
+        })
+    }
+
+    /// The outcomes of moving from `block` along the low fidelity `action` as a list of
+    /// (block, probability)
+    fn low_res_step(&self, block: LowResState, action: i32) -> PyResult<Vec<(LowResState, f64)>> {
+        self.with_info(|info| {
+            let warehouse: Robot<LowResState, LowResWord> = Robot::make(4, Default::default());
+            let outcomes = warehouse.step(&block, action, info)?;
+            Ok(outcomes.into_iter().map(|(s, p, _)| (s, p)).collect())
+        })
+    }
+
+    /// The layout as text with the agents in `states`, see `Info::render_ascii`
+    #[pyo3(signature = (states = Vec::new()))]
+    fn render(&self, states: Vec<StateTuple>) -> PyResult<String> {
+        let agents = states.into_iter().map(state_from_py).collect::<PyResult<Vec<State>>>()?;
+        self.with_info(|info| Ok(info.render_ascii(&Overlay::new(agents))))
+    }
+}
+
+/// A high fidelity task DFA
+#[pyclass(name = "TaskDfa")]
+pub struct PyTaskDfa {
+    dfa: TaskDfa
+}
+
+#[pymethods]
+impl PyTaskDfa {
+    #[staticmethod]
+    fn replenishment() -> PyTaskDfa {
+        PyTaskDfa { dfa: TaskDfa::replenishment() }
+    }
+
+    #[staticmethod]
+    fn regeneration() -> PyTaskDfa {
+        PyTaskDfa { dfa: TaskDfa::regeneration() }
+    }
+
+    #[staticmethod]
+    fn recharge() -> PyTaskDfa {
+        PyTaskDfa { dfa: TaskDfa::recharge() }
+    }
+
+    #[getter]
+    fn init(&self) -> i32 {
+        self.dfa.init
+    }
+
+    #[getter]
+    fn num_states(&self) -> i32 {
+        self.dfa.num_states
+    }
+
+    #[getter]
+    fn accepting(&self) -> Vec<i32> {
+        self.dfa.accepting.to_vec()
+    }
+
+    #[getter]
+    fn rejecting(&self) -> Vec<i32> {
+        self.dfa.rejecting.to_vec()
+    }
+
+    fn is_complete(&self, q: i32) -> bool {
+        self.dfa.is_complete(q)
+    }
+
+    fn is_rejecting(&self, q: i32) -> bool {
+        self.dfa.is_rejecting(q)
+    }
+
+    /// The DFA state after entering `state` from `q`, with the task parameters of `warehouse`
+    fn next(&self, warehouse: &PyWarehouse, q: i32, state: StateTuple) -> PyResult<i32> {
+        let state = state_from_py(state)?;
+        warehouse.with_info(|info| Ok((self.dfa.next)(q, &WarehouseWord::from_state(&state, info), info)))
+    }
+}
+
+/// A scheduler written by the plan or regen stage
+#[pyclass(name = "Scheduler")]
+pub struct PyScheduler {
+    policy: Policy
+}
+
+#[pymethods]
+impl PyScheduler {
+    /// Load a scheduler in the binary or the JSON debug format
+    #[staticmethod]
+    fn from_file(path: &str) -> PyResult<PyScheduler> {
+        Ok(PyScheduler { policy: Policy::from_file(path)? })
+    }
+
+    /// Raise a ValueError if the scheduler was synthesised for a different layout
+    fn check_layout(&self, warehouse: &PyWarehouse) -> PyResult<()> {
+        warehouse.with_info(|info| self.policy.header.check_layout(info))
+    }
+
+    #[getter]
+    fn dfa_init(&self) -> i32 {
+        self.policy.header.dfa_init
+    }
+
+    #[getter]
+    fn dfa_states(&self) -> u32 {
+        self.policy.header.dfa_states
+    }
+
+    /// The action of the scheduler in `state` and DFA state `q`, None if it never reaches them
+    fn action(&self, state: StateTuple, q: i32) -> PyResult<Option<i32>> {
+        Ok(self.policy.action(&state_from_py(state)?, q).map(|a| a.index()))
+    }
+
+    fn __len__(&self) -> usize {
+        self.policy.len()
+    }
+}
+
+#[pymodule]
+fn rusty_robots(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyWarehouse>()?;
+    m.add_class::<PyTaskDfa>()?;
+    m.add_class::<PyScheduler>()?;
+    m.add("ACTIONS", WarehouseAction::ALL.iter().map(|a| a.name()).collect::<Vec<&str>>())?;
+    m.add("HEADINGS", Heading::ALL.iter().map(|h| h.name()).collect::<Vec<&str>>())?;
+    Ok(())
+}
@@ -0,0 +1,375 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use crate::env::reward::WarehouseRewardModel;
+use crate::env::warehouse::high_fidelity_warehouse::{BatteryModel, MotionModel, Point};
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Parse(String),
+    Invalid(String),
+    /// `--help` was requested, holds the usage message
+    Help(String)
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => { write!(f, "unable to read scenario: {}", e) }
+            ScenarioError::Parse(e) => { write!(f, "unable to parse scenario: {}", e) }
+            ScenarioError::Invalid(e) => { write!(f, "invalid scenario: {}", e) }
+            ScenarioError::Help(usage) => { write!(f, "{}", usage) }
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError { }
+
+impl From<std::io::Error> for ScenarioError {
+    fn from(e: std::io::Error) -> Self {
+        ScenarioError::Io(e)
+    }
+}
+
+/// The parameters of a warehouse experiment. A scenario is read from a YAML or JSON file and any
+/// field can be overridden on the command line, fields missing from the file take the values of
+/// the original 12x12 experiment.
+///
+/// If a `layout` is given the grid dimensions, feed points, queue points and agent starts are
+/// taken from the layout, falling back to the scenario values for any the layout does not declare.
+/// Without `agent_starts` the agents start along the top and bottom walls from column 2.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Scenario {
+    pub agents: usize,
+    pub tasks: usize,
+    pub width: usize,
+    pub height: usize,
+    pub layout: Option<String>,
+    pub feed_points: Vec<Point>,
+    pub queue_points: Vec<Point>,
+    pub agent_starts: Option<Vec<Point>>,
+    pub grid_square: usize,
+    /// Action outcome uncertainty of the high fidelity warehouse, deterministic by default
+    pub motion_model: MotionModel,
+    /// The battery of the high fidelity agents, the battery is not modelled without one
+    pub battery: Option<BatteryModel>,
+    /// The chance that a low fidelity move is held up, derived from the motion model when not given
+    pub low_res_slip: Option<f64>,
+    /// The congestion of low fidelity blocks as (block, probability) pairs, see `LowResNoise`
+    pub congestion: Vec<(Point, f64)>,
+    /// The costs of both warehouses, a time cost of 1 per action by default
+    pub rewards: WarehouseRewardModel,
+    /// Seed for the choice of task racks and feed points
+    pub seed: u64,
+    /// Seed for sampling an allocation from the randomised task witness, `None` samples from
+    /// entropy
+    pub allocation_seed: Option<u64>,
+    /// The cost target of every agent
+    pub cost_target: f64,
+    /// The probability target of every task
+    pub prob_target: f64,
+    /// Value iteration tolerance for the low fidelity SCPM
+    pub eps: f64,
+    /// Value iteration tolerance for the high fidelity products
+    pub hr_eps: f64,
+    pub threads_save: usize,
+    pub threads_load: usize,
+    /// Trajectories sampled per scheduler by the evaluate stage
+    pub episodes: usize
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            agents: 4,
+            tasks: 9,
+            width: 12,
+            height: 12,
+            layout: None,
+            feed_points: vec![(0, 5)],
+            queue_points: vec![(11, 11), (0, 11), (3, 11), (9, 0)],
+            agent_starts: None,
+            grid_square: 1,
+            motion_model: Default::default(),
+            battery: None,
+            low_res_slip: None,
+            congestion: Vec::new(),
+            rewards: Default::default(),
+            seed: 1234,
+            allocation_seed: None,
+            cost_target: -15.,
+            prob_target: 0.99,
+            eps: 0.0001,
+            hr_eps: 1e-5,
+            threads_save: 30,
+            threads_load: 10,
+            episodes: 1000
+        }
+    }
+}
+
+pub const USAGE: &str = "\
+usage: warehouse [--scenario FILE] [OPTIONS]
+
+  --scenario FILE         read the scenario from a YAML or JSON file
+  --agents N              number of agents
+  --tasks N               number of tasks
+  --width N               grid width when no layout is given
+  --height N              grid height when no layout is given
+  --layout FILE           warehouse layout file (YAML, JSON or ASCII map)
+  --grid-square N         cells along each side of a low fidelity block
+  --slip-stay X           probability that a forward move leaves the agent where it is
+  --slip-drift X          probability that a forward move drifts to the left or right
+  --pickup-fail X         probability that a pickup fails
+  --drop-fail X           probability that a place fails
+  --rotation-overshoot X  probability that a rotation turns the agent around
+  --low-res-slip X        probability that a low fidelity move is held up, derived from the motion
+                          model by default
+  --seed N                seed for choosing the task racks and feed points
+  --allocation-seed N     seed for sampling the task allocation
+  --cost-target X         cost target of every agent
+  --prob-target X         probability target of every task
+  --eps X                 low fidelity value iteration tolerance
+  --hr-eps X              high fidelity value iteration tolerance
+  --threads-save N        threads used to write schedulers
+  --threads-load N        threads used to construct the SCPM
+  --episodes N            trajectories sampled per scheduler by the evaluate stage
+  -h, --help              print this message
+";
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, ScenarioError> {
+    let value = value.ok_or_else(|| ScenarioError::Parse(format!("{} requires a value", flag)))?;
+    value.parse::<T>()
+        .map_err(|_| ScenarioError::Parse(format!("invalid value '{}' for {}", value, flag)))
+}
+
+/// 64 bit FNV-1a, stable across platforms and releases unlike the std hasher
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+impl Scenario {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Scenario, ScenarioError> {
+        let contents = fs::read_to_string(path.as_ref())?;
+        let scenario: Scenario = match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("json") => {
+                serde_json::from_str(&contents).map_err(|e| ScenarioError::Parse(e.to_string()))?
+            }
+            _ => {
+                serde_yaml::from_str(&contents).map_err(|e| ScenarioError::Parse(e.to_string()))?
+            }
+        };
+        Ok(scenario)
+    }
+
+    /// Build a scenario from command line arguments (without the program name): the scenario
+    /// file given by `--scenario`, or the defaults, with every other flag applied on top
+    pub fn from_args<I: IntoIterator<Item=String>>(args: I) -> Result<Scenario, ScenarioError> {
+        let args: Vec<String> = args.into_iter().collect();
+        let mut scenario = match args.iter().position(|a| a == "--scenario") {
+            Some(i) => {
+                let path: String = parse_value("--scenario", args.get(i + 1).cloned())?;
+                Scenario::from_file(path)?
+            }
+            None => { Default::default() }
+        };
+        scenario.apply_overrides(args)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn apply_overrides<I: IntoIterator<Item=String>>(&mut self, args: I) -> Result<(), ScenarioError> {
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            match flag.as_str() {
+                "--scenario" => { args.next(); }
+                "--agents" => { self.agents = parse_value(&flag, args.next())?; }
+                "--tasks" => { self.tasks = parse_value(&flag, args.next())?; }
+                "--width" => { self.width = parse_value(&flag, args.next())?; }
+                "--height" => { self.height = parse_value(&flag, args.next())?; }
+                "--layout" => { self.layout = Some(parse_value(&flag, args.next())?); }
+                "--grid-square" => { self.grid_square = parse_value(&flag, args.next())?; }
+                "--slip-stay" => { self.motion_model.slip_stay = parse_value(&flag, args.next())?; }
+                "--slip-drift" => { self.motion_model.slip_drift = parse_value(&flag, args.next())?; }
+                "--pickup-fail" => { self.motion_model.pickup_fail = parse_value(&flag, args.next())?; }
+                "--drop-fail" => { self.motion_model.drop_fail = parse_value(&flag, args.next())?; }
+                "--rotation-overshoot" => {
+                    self.motion_model.rotation_overshoot = parse_value(&flag, args.next())?;
+                }
+                "--low-res-slip" => { self.low_res_slip = Some(parse_value(&flag, args.next())?); }
+                "--seed" => { self.seed = parse_value(&flag, args.next())?; }
+                "--allocation-seed" => { self.allocation_seed = Some(parse_value(&flag, args.next())?); }
+                "--cost-target" => { self.cost_target = parse_value(&flag, args.next())?; }
+                "--prob-target" => { self.prob_target = parse_value(&flag, args.next())?; }
+                "--eps" => { self.eps = parse_value(&flag, args.next())?; }
+                "--hr-eps" => { self.hr_eps = parse_value(&flag, args.next())?; }
+                "--threads-save" => { self.threads_save = parse_value(&flag, args.next())?; }
+                "--threads-load" => { self.threads_load = parse_value(&flag, args.next())?; }
+                "--episodes" => { self.episodes = parse_value(&flag, args.next())?; }
+                "-h" | "--help" => { return Err(ScenarioError::Help(USAGE.to_string())) }
+                _ => {
+                    return Err(ScenarioError::Parse(format!("unknown argument '{}'\n\n{}", flag, USAGE)))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        if self.agents == 0 || self.tasks == 0 {
+            return Err(ScenarioError::Invalid("there must be at least one agent and one task".to_string()))
+        }
+        if self.layout.is_none() {
+            if self.width < 5 || self.height < 5 {
+                return Err(ScenarioError::Invalid("the grid must be at least 5x5 to fit any racks".to_string()))
+            }
+            // the racks generated by `Info::set_racks`, two per row of each rack column
+            let racks = (self.width - 2) / 3 * (self.height - 4) * 2;
+            if self.tasks > racks {
+                return Err(ScenarioError::Invalid(format!(
+                    "{} tasks need as many racks, a {}x{} grid has {}", self.tasks, self.width, self.height, racks
+                )))
+            }
+            if self.feed_points.is_empty() {
+                return Err(ScenarioError::Invalid("at least one feed point is needed".to_string()))
+            }
+        }
+        if self.grid_square == 0 {
+            return Err(ScenarioError::Invalid("grid_square must be at least 1".to_string()))
+        }
+        if !self.motion_model.is_valid() {
+            return Err(ScenarioError::Invalid(
+                "motion model probabilities must be in [0, 1] and slip_stay + slip_drift must not exceed 1".to_string()
+            ))
+        }
+        if let Some(battery) = &self.battery {
+            if battery.levels == 0 {
+                return Err(ScenarioError::Invalid("the battery must have at least one level".to_string()))
+            }
+            if !(0. ..=100.).contains(&battery.reserve) {
+                return Err(ScenarioError::Invalid("the battery reserve must be a percentage".to_string()))
+            }
+        }
+        if !self.low_res_slip.map_or(true, |p| (0. ..=1.).contains(&p)) {
+            return Err(ScenarioError::Invalid("low_res_slip must be in [0, 1]".to_string()))
+        }
+        if self.congestion.iter().any(|(_, c)| !(0. ..=1.).contains(c)) {
+            return Err(ScenarioError::Invalid("block congestion must be in [0, 1]".to_string()))
+        }
+        if !(0. ..=1.).contains(&self.prob_target) {
+            return Err(ScenarioError::Invalid("prob_target must be in [0, 1]".to_string()))
+        }
+        if self.eps <= 0. || self.hr_eps <= 0. {
+            return Err(ScenarioError::Invalid("eps and hr_eps must be positive".to_string()))
+        }
+        if self.threads_save == 0 || self.threads_load == 0 {
+            return Err(ScenarioError::Invalid("thread counts must be at least 1".to_string()))
+        }
+        if self.episodes == 0 {
+            return Err(ScenarioError::Invalid("episodes must be at least 1".to_string()))
+        }
+        Ok(())
+    }
+
+    /// A hash of every parameter recorded by `build-scpm` which changes the outcome of the
+    /// experiment, including the motion model, the battery, the low fidelity noise and the reward
+    /// model, and of the layout file if there is one. The parameters taken from the command line by
+    /// the later stages are hashed per stage by `stage_fingerprint`, and thread counts and the
+    /// number of evaluation episodes are not included. Fails if the layout file can not be read.
+    pub fn fingerprint(&self) -> Result<u64, std::io::Error> {
+        let scenario = Scenario {
+            threads_save: 0,
+            threads_load: 0,
+            episodes: 0,
+            eps: 0.,
+            hr_eps: 0.,
+            allocation_seed: None,
+            cost_target: 0.,
+            prob_target: 0.,
+            ..self.clone()
+        };
+        let mut bytes = serde_json::to_vec(&scenario).map_err(std::io::Error::from)?;
+        if let Some(layout) = &self.layout {
+            bytes.extend(fs::read(layout)?);
+        }
+        Ok(fnv1a(&bytes))
+    }
+
+    /// A hash of the command line parameters `stage` depends on: the low fidelity tolerance and
+    /// the targets of the solve, the allocation seed and the high fidelity tolerance of the
+    /// schedulers. A stage which depends on none of them hashes nothing.
+    pub fn stage_fingerprint(&self, stage: &str) -> u64 {
+        let bytes: Vec<u8> = match stage {
+            "imovi" | "solve" => {
+                [self.eps, self.cost_target, self.prob_target].iter().flat_map(|x| x.to_le_bytes()).collect()
+            }
+            "allocate" => { self.allocation_seed.map_or(Vec::new(), |seed| seed.to_le_bytes().to_vec()) }
+            "plan" | "regen" => { self.hr_eps.to_le_bytes().to_vec() }
+            _ => { Vec::new() }
+        };
+        fnv1a(&bytes)
+    }
+
+    /// The agent start positions: the given starts, or starts along the top and bottom walls of a
+    /// `w` x `h` grid from column 2
+    pub fn agent_start_positions(&self, w: usize, h: usize) -> Vec<Point> {
+        match &self.agent_starts {
+            Some(starts) => { starts.to_vec() }
+            None => {
+                let mut starts: Vec<Point> = Vec::new();
+                for x in 2..w as i32 {
+                    if starts.len() < self.agents {
+                        starts.push((x, 0));
+                        starts.push((x, h as i32 - 1));
+                    }
+                }
+                starts
+            }
+        }
+    }
+
+    /// The agent targets followed by the task targets, as expected by the SCPM solvers
+    pub fn targets(&self) -> Vec<f64> {
+        let mut target = vec![self.cost_target; self.agents];
+        target.append(&mut vec![self.prob_target; self.tasks]);
+        target
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_parameters_are_fingerprinted_per_stage() {
+        let scenario = Scenario::default();
+        let cli = Scenario { eps: 1e-3, hr_eps: 1e-3, allocation_seed: Some(7), threads_save: 2, ..scenario.clone() };
+        assert_eq!(scenario.fingerprint().unwrap(), cli.fingerprint().unwrap());
+        assert_ne!(scenario.stage_fingerprint("solve"), cli.stage_fingerprint("solve"));
+        assert_ne!(scenario.stage_fingerprint("allocate"), cli.stage_fingerprint("allocate"));
+        assert_ne!(scenario.stage_fingerprint("plan"), cli.stage_fingerprint("plan"));
+        assert_eq!(scenario.stage_fingerprint("build-scpm"), cli.stage_fingerprint("build-scpm"));
+        let seeded = Scenario { seed: scenario.seed + 1, ..scenario.clone() };
+        assert_ne!(scenario.fingerprint().unwrap(), seeded.fingerprint().unwrap());
+    }
+
+    #[test]
+    fn the_grid_must_fit_the_tasks() {
+        assert!(Scenario::default().validate().is_ok());
+        let crowded = Scenario { width: 5, height: 5, tasks: 3, ..Default::default() };
+        assert!(matches!(crowded.validate(), Err(ScenarioError::Invalid(_))));
+        let unfed = Scenario { feed_points: Vec::new(), ..Default::default() };
+        assert!(matches!(unfed.validate(), Err(ScenarioError::Invalid(_))));
+    }
+
+    #[test]
+    fn a_missing_layout_file_is_not_fingerprinted() {
+        let scenario = Scenario { layout: Some("layouts/does_not_exist.yaml".to_string()), ..Default::default() };
+        assert!(scenario.fingerprint().is_err());
+        let scenario = Scenario { layout: Some("layouts/warehouse_12x12.yaml".to_string()), ..Default::default() };
+        assert!(scenario.fingerprint().is_ok());
+    }
+}
@@ -0,0 +1,302 @@
+use std::io::{self, Read, Write};
+use crate::env::warehouse::high_fidelity_warehouse::{Info, MAX_PODS, Point, State};
+use crate::env::warehouse::tasks::TaskDfa;
+use crate::error::Error;
+use crate::scenario::fnv1a;
+use crate::scheduler::policy::Policy;
+
+/*
+The binary scheduler format, every integer is little endian:
+magic        4 bytes "RRPL"
+version      u16, POLICY_FORMAT_VERSION
+max pods     u16, the MAX_PODS the states were written with
+width        u32, the layout the scheduler was synthesised for
+height       u32
+layout hash  u64, see `PolicyHeader::for_task`, 0 if unknown
+dfa init     i32, the initial state of the task DFA
+dfa states   u32, DFA states are 0..dfa states
+num states   u32, the robot states of the table
+states       num states entries of: agent_dir u8, agent_position 2 x i32, carrying u8,
+             packs MAX_PODS x 2 x i32, empty_racks MAX_PODS x 2 x i32, battery u8
+actions      num states x dfa states u8 action indices, row major by state, 255 where the
+             scheduler has no action
+
+The counts of a header are not trusted when reading: the DFA size is capped, the states must lie
+in the layout of the header when it is known, and the states and actions are read as they arrive
+rather than allocated up front, so an inflated count fails at the end of the file.
+*/
+
+pub const POLICY_MAGIC: [u8; 4] = *b"RRPL";
+pub const POLICY_FORMAT_VERSION: u16 = 1;
+/// The most DFA states a scheduler file may be written for, the task DFAs have at most 8
+pub const MAX_DFA_STATES: u32 = 1024;
+
+/// The layout and task DFA a scheduler was synthesised for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PolicyHeader {
+    pub width: u32,
+    pub height: u32,
+    pub layout_hash: u64,
+    pub dfa_init: i32,
+    pub dfa_states: u32
+}
+
+/// A hash of the cells which a scheduler depends on: the grid size, racks, feed points,
+/// obstacles, walls and inventory racks
+fn layout_hash(info: &Info) -> u64 {
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend((info.width as u32).to_le_bytes());
+    bytes.extend((info.height as u32).to_le_bytes());
+    let groups: [&[Point]; 5] = [
+        &info.rack_positions[..],
+        info.feed_points,
+        &info.obstacle_positions[..],
+        &info.wall_positions[..],
+        &info.inventory_racks[..]
+    ];
+    for points in groups.iter() {
+        bytes.extend((points.len() as u32).to_le_bytes());
+        for p in points.iter() {
+            bytes.extend(p.0.to_le_bytes());
+            bytes.extend(p.1.to_le_bytes());
+        }
+    }
+    fnv1a(&bytes)
+}
+
+impl PolicyHeader {
+    pub fn for_task(info: &Info, dfa: &TaskDfa) -> PolicyHeader {
+        PolicyHeader {
+            width: info.width as u32,
+            height: info.height as u32,
+            layout_hash: layout_hash(info),
+            dfa_init: dfa.init,
+            dfa_states: dfa.num_states as u32
+        }
+    }
+
+    /// Check that the scheduler was written for the layout of `info`, a header without a layout
+    /// hash matches any layout
+    pub fn check_layout(&self, info: &Info) -> Result<(), Error> {
+        if self.layout_hash != 0 && self.layout_hash != layout_hash(info) {
+            return Err(Error::InvalidLayout(format!(
+                "the scheduler was synthesised for a different {}x{} layout", self.width, self.height
+            )))
+        }
+        Ok(())
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+fn write_point<W: Write>(w: &mut W, p: &Point) -> io::Result<()> {
+    w.write_all(&p.0.to_le_bytes())?;
+    w.write_all(&p.1.to_le_bytes())
+}
+
+fn read_array<R: Read, const N: usize>(r: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_point<R: Read>(r: &mut R) -> io::Result<Point> {
+    Ok((i32::from_le_bytes(read_array(r)?), i32::from_le_bytes(read_array(r)?)))
+}
+
+/// The number of entries of the action table of a header with `num_states` states, the DFA must
+/// have between 1 and MAX_DFA_STATES states, start in one of them, and the table must be addressable
+fn table_len(header: &PolicyHeader, num_states: u32) -> Result<usize, Error> {
+    if header.dfa_states == 0 || header.dfa_states > MAX_DFA_STATES {
+        return Err(invalid(format!(
+            "the scheduler has {} DFA states, expected 1 to {}", header.dfa_states, MAX_DFA_STATES
+        )))
+    }
+    if header.dfa_init < 0 || header.dfa_init as u32 >= header.dfa_states {
+        return Err(invalid(format!(
+            "the initial DFA state {} is not one of the {} DFA states", header.dfa_init, header.dfa_states
+        )))
+    }
+    (num_states as usize).checked_mul(header.dfa_states as usize)
+        .ok_or_else(|| invalid(format!(
+            "{} states x {} DFA states is too large for an action table", num_states, header.dfa_states
+        )))
+}
+
+impl Policy {
+    pub fn write_binary<W: Write>(&self, mut w: W) -> Result<(), Error> {
+        let header = &self.header;
+        w.write_all(&POLICY_MAGIC)?;
+        w.write_all(&POLICY_FORMAT_VERSION.to_le_bytes())?;
+        w.write_all(&(MAX_PODS as u16).to_le_bytes())?;
+        w.write_all(&header.width.to_le_bytes())?;
+        w.write_all(&header.height.to_le_bytes())?;
+        w.write_all(&header.layout_hash.to_le_bytes())?;
+        w.write_all(&header.dfa_init.to_le_bytes())?;
+        w.write_all(&header.dfa_states.to_le_bytes())?;
+        w.write_all(&(self.states.len() as u32).to_le_bytes())?;
+        for s in self.states.iter() {
+            w.write_all(&[s.agent_dir])?;
+            write_point(&mut w, &s.agent_position)?;
+            w.write_all(&[s.carrying])?;
+            for p in s.packs.iter().chain(s.empty_racks.iter()) {
+                write_point(&mut w, p)?;
+            }
+            w.write_all(&[s.battery])?;
+        }
+        w.write_all(&self.actions[..])?;
+        w.flush()?;
+        Ok(())
+    }
+
+    pub fn read_binary<R: Read>(mut r: R) -> Result<Policy, Error> {
+        let magic: [u8; 4] = read_array(&mut r)?;
+        if magic != POLICY_MAGIC {
+            return Err(invalid("not a binary scheduler".to_string()))
+        }
+        let version = u16::from_le_bytes(read_array(&mut r)?);
+        if version != POLICY_FORMAT_VERSION {
+            return Err(invalid(format!(
+                "the scheduler has format version {}, expected version {}", version, POLICY_FORMAT_VERSION
+            )))
+        }
+        let max_pods = u16::from_le_bytes(read_array(&mut r)?) as usize;
+        if max_pods != MAX_PODS {
+            return Err(invalid(format!(
+                "the scheduler records {} pods per state, expected {}", max_pods, MAX_PODS
+            )))
+        }
+        let header = PolicyHeader {
+            width: u32::from_le_bytes(read_array(&mut r)?),
+            height: u32::from_le_bytes(read_array(&mut r)?),
+            layout_hash: u64::from_le_bytes(read_array(&mut r)?),
+            dfa_init: i32::from_le_bytes(read_array(&mut r)?),
+            dfa_states: u32::from_le_bytes(read_array(&mut r)?)
+        };
+        let num_states = u32::from_le_bytes(read_array(&mut r)?);
+        let table_len = table_len(&header, num_states)?;
+        let in_layout = |p: &Point| {
+            header.width == 0 || header.height == 0
+                || (p.0 >= 0 && p.1 >= 0 && (p.0 as u32) < header.width && (p.1 as u32) < header.height)
+        };
+        let mut states: Vec<State> = Vec::new();
+        for _ in 0..num_states {
+            let [agent_dir] = read_array(&mut r)?;
+            let agent_position = read_point(&mut r)?;
+            if agent_dir >= 4 || !in_layout(&agent_position) {
+                return Err(invalid(format!(
+                    "state {} has the agent at {:?} facing {}, outside the {}x{} layout",
+                    states.len(), agent_position, agent_dir, header.width, header.height
+                )))
+            }
+            let [carrying] = read_array(&mut r)?;
+            let mut state = State { agent_dir, agent_position, carrying, ..Default::default() };
+            for p in state.packs.iter_mut().chain(state.empty_racks.iter_mut()) {
+                *p = read_point(&mut r)?;
+            }
+            let [battery] = read_array(&mut r)?;
+            state.battery = battery;
+            states.push(state);
+        }
+        let mut actions: Vec<u8> = Vec::new();
+        r.take(table_len as u64).read_to_end(&mut actions)?;
+        if actions.len() != table_len {
+            return Err(invalid(format!(
+                "the action table has {} of {} entries", actions.len(), table_len
+            )))
+        }
+        Policy::from_table(header, states, actions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::warehouse::action::WarehouseAction;
+    use super::*;
+
+    fn policy() -> Policy {
+        let header = PolicyHeader { width: 4, height: 3, layout_hash: 7, dfa_init: 0, dfa_states: 3 };
+        let mut policy = Policy::new(header);
+        let mut carrying = State { agent_dir: 2, agent_position: (3, 2), carrying: 1, battery: 5, ..Default::default() };
+        carrying.add_pack((1, 0));
+        carrying.empty_rack((1, 1));
+        policy.insert(State { agent_position: (0, 0), ..Default::default() }, 0, WarehouseAction::Forward).unwrap();
+        policy.insert(State { agent_position: (0, 0), ..Default::default() }, 2, WarehouseAction::RotateLeft).unwrap();
+        policy.insert(carrying, 1, WarehouseAction::Place).unwrap();
+        policy
+    }
+
+    fn to_bytes(policy: &Policy) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        policy.write_binary(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn is_invalid_data<T>(result: Result<T, Error>) -> bool {
+        matches!(result, Err(Error::Io(e)) if e.kind() == io::ErrorKind::InvalidData)
+    }
+
+    #[test]
+    fn round_trip() {
+        let policy = policy();
+        let read = Policy::read_binary(&to_bytes(&policy)[..]).unwrap();
+        assert_eq!(read.header, policy.header);
+        assert_eq!(read.len(), policy.len());
+        for (state, q, action) in policy.entries() {
+            assert_eq!(read.action(&state, q), Some(action));
+        }
+        assert_eq!(read.action(&State { agent_position: (0, 0), ..Default::default() }, 1), None);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let bytes = to_bytes(&policy());
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert!(is_invalid_data(Policy::read_binary(&magic[..])));
+        let mut version = bytes.clone();
+        version[4..6].copy_from_slice(&(POLICY_FORMAT_VERSION + 1).to_le_bytes());
+        assert!(is_invalid_data(Policy::read_binary(&version[..])));
+        let mut pods = bytes.clone();
+        pods[6..8].copy_from_slice(&(MAX_PODS as u16 + 1).to_le_bytes());
+        assert!(is_invalid_data(Policy::read_binary(&pods[..])));
+        let mut dfa_states = bytes.clone();
+        dfa_states[28..32].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(is_invalid_data(Policy::read_binary(&dfa_states[..])));
+        for dfa_init in [-1i32, 3] {
+            let mut init = bytes.clone();
+            init[24..28].copy_from_slice(&dfa_init.to_le_bytes());
+            assert!(is_invalid_data(Policy::read_binary(&init[..])));
+        }
+        // an inflated state count runs out of data rather than allocating the table
+        let mut num_states = bytes.clone();
+        num_states[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Policy::read_binary(&num_states[..]).is_err());
+        assert!(Policy::read_binary(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn from_file_detects_the_format() {
+        let dir = std::env::temp_dir();
+        let binary = dir.join(format!("rusty_robots_policy_{}.bin", std::process::id()));
+        let json = binary.with_extension("json");
+        policy().to_file(&binary).unwrap();
+        // shorter than the magic
+        std::fs::write(&json, "{}").unwrap();
+        let (read_binary, read_json) = (Policy::from_file(&binary), Policy::from_file(&json));
+        std::fs::remove_file(&binary).unwrap();
+        std::fs::remove_file(&json).unwrap();
+        assert_eq!(read_binary.unwrap().len(), 3);
+        assert!(read_json.unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_actions() {
+        let mut bytes = to_bytes(&policy());
+        let n = bytes.len();
+        bytes[n - 1] = WarehouseAction::ALL.len() as u8;
+        assert!(matches!(Policy::read_binary(&bytes[..]), Err(Error::UnknownAction(5))));
+    }
+}
@@ -0,0 +1,242 @@
+use rand::Rng;
+use scpm::agent::Robot;
+use serde::{Serialize, Deserialize};
+use crate::env::reward::RewardModel;
+use crate::env::gym_env::{Env, sample_outcome};
+use crate::env::warehouse::high_fidelity_warehouse::{Info, State, WarehouseWord};
+use crate::error::Error;
+use crate::scheduler::executor::{ExecutionStatus, Executor};
+
+/// A sample mean with a normal approximation confidence interval
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    pub mean: f64,
+    pub lower: f64,
+    pub upper: f64,
+    pub samples: usize
+}
+
+impl Estimate {
+    /// The mean of `samples` with the interval mean +/- z * standard error
+    pub fn mean(samples: &[f64], z: f64) -> Estimate {
+        let n = samples.len();
+        if n == 0 {
+            return Estimate { mean: 0., lower: 0., upper: 0., samples: 0 }
+        }
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        let variance = if n > 1 {
+            samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1) as f64
+        } else {
+            0.
+        };
+        let half_width = z * (variance / n as f64).sqrt();
+        Estimate { mean, lower: mean - half_width, upper: mean + half_width, samples: n }
+    }
+
+    /// The proportion of `successes` in `n` trials with the Wilson score interval, which stays
+    /// inside [0, 1] and does not collapse when every trial succeeds
+    pub fn proportion(successes: usize, n: usize, z: f64) -> Estimate {
+        if n == 0 {
+            return Estimate { mean: 0., lower: 0., upper: 1., samples: 0 }
+        }
+        let nf = n as f64;
+        let p = successes as f64 / nf;
+        let z2 = z * z;
+        let centre = (p + z2 / (2. * nf)) / (1. + z2 / nf);
+        let half_width = z * (p * (1. - p) / nf + z2 / (4. * nf * nf)).sqrt() / (1. + z2 / nf);
+        Estimate {
+            mean: p,
+            lower: (centre - half_width).max(0.),
+            upper: (centre + half_width).min(1.),
+            samples: n
+        }
+    }
+
+    pub fn contains(&self, x: f64) -> bool {
+        self.lower <= x && x <= self.upper
+    }
+}
+
+/// The empirical cost and success probability of a scheduler, with the values the solvers
+/// predicted for it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Evaluation {
+    pub agent: usize,
+    pub task: usize,
+    /// The total scalarised reward of a trajectory
+    pub cost: Estimate,
+    /// The probability that a trajectory reaches an accepting state of the task DFA
+    pub success: Estimate,
+    /// Trajectories which were cut off at the step limit, they count as failures
+    pub truncated: usize,
+    /// The expected cost of the high fidelity product scheduler
+    pub predicted_cost: Option<f64>,
+    /// The expected cost and probability of the low fidelity SCPM scheduler that allocated the task
+    pub predicted_scpm_cost: Option<f64>,
+    pub predicted_scpm_prob: Option<f64>
+}
+
+impl Evaluation {
+    /// Whether the predicted cost of the product scheduler is inside the confidence interval
+    pub fn cost_consistent(&self) -> Option<bool> {
+        self.predicted_cost.map(|c| self.cost.contains(c))
+    }
+
+    /// Whether the predicted probability of the SCPM scheduler is inside the confidence interval
+    pub fn prob_consistent(&self) -> Option<bool> {
+        self.predicted_scpm_prob.map(|p| self.success.contains(p))
+    }
+}
+
+/// Samples trajectories of the product of the warehouse and a task DFA under a scheduler. The
+/// outcome of each action is drawn from `Env::step`, so stochastic motion models are
+/// evaluated as they are modelled.
+#[derive(Clone, Copy, Debug)]
+pub struct MonteCarlo {
+    pub episodes: usize,
+    pub max_steps: usize,
+    /// The standard normal quantile of the confidence intervals, 1.96 for 95%
+    pub z: f64
+}
+
+impl MonteCarlo {
+    pub fn new(episodes: usize, max_steps: usize) -> MonteCarlo {
+        MonteCarlo { episodes, max_steps, z: 1.96 }
+    }
+
+    /// Sample one trajectory from `init`, returns the cost, whether the task was accepted, and
+    /// whether the trajectory reached the step limit
+    pub fn episode<R: Rng>(
+        &self,
+        warehouse: &Robot<State, WarehouseWord>,
+        executor: &mut Executor,
+        init: State,
+        info: &Info,
+        rewards: &dyn RewardModel<State>,
+        rng: &mut R
+    ) -> Result<(f64, bool, bool), Error> {
+        executor.reset(init);
+        let mut cost: f64 = 0.;
+        for _ in 0..self.max_steps {
+            let action = match executor.action() {
+                Some(a) => { a }
+                None => {
+                    let accepted = executor.status() == ExecutionStatus::Complete
+                        && executor.task.accepting.contains(&executor.q);
+                    return Ok((cost, accepted, false))
+                }
+            };
+            cost += rewards.scalarise(&rewards.reward(&executor.state, action.index(), info)[..]);
+            let outcomes = warehouse.step(&executor.state, action.index(), info)?;
+            let (next, _, _) = sample_outcome(&outcomes, rng)?;
+            executor.observe(*next, info);
+        }
+        Ok((cost, false, true))
+    }
+
+    /// Estimate the cost and success probability of the scheduler of `executor` for `(agent, task)`,
+    /// with `info` set up for the task. Every trajectory starts from the state the executor
+    /// is in, and the predictions are filled in by the caller.
+    pub fn evaluate<R: Rng>(
+        &self,
+        (agent, task): (usize, usize),
+        warehouse: &Robot<State, WarehouseWord>,
+        executor: &mut Executor,
+        info: &Info,
+        rewards: &dyn RewardModel<State>,
+        rng: &mut R
+    ) -> Result<Evaluation, Error> {
+        let init = executor.state;
+        let mut costs: Vec<f64> = Vec::with_capacity(self.episodes);
+        let mut successes: usize = 0;
+        let mut truncated: usize = 0;
+        for _ in 0..self.episodes {
+            let (cost, accepted, cut_off) = self.episode(warehouse, executor, init, info, rewards, rng)?;
+            costs.push(cost);
+            successes += accepted as usize;
+            truncated += cut_off as usize;
+        }
+        Ok(Evaluation {
+            agent,
+            task,
+            cost: Estimate::mean(&costs[..], self.z),
+            success: Estimate::proportion(successes, self.episodes, self.z),
+            truncated,
+            predicted_cost: None,
+            predicted_scpm_cost: None,
+            predicted_scpm_prob: None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::reward::ConstantReward;
+    use crate::env::warehouse::action::WarehouseAction;
+    use crate::env::warehouse::high_fidelity_warehouse::warehouse_defaults;
+    use crate::env::warehouse::layout::WarehouseLayout;
+    use crate::env::warehouse::tasks::TaskDfa;
+    use crate::scheduler::binary::PolicyHeader;
+    use crate::scheduler::policy::Policy;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn wilson_bounds() {
+        let none = Estimate::proportion(0, 10, 1.96);
+        assert_eq!((none.mean, none.lower), (0., 0.));
+        assert!(close(none.upper, 0.27754));
+        let all = Estimate::proportion(10, 10, 1.96);
+        assert_eq!((all.mean, all.upper), (1., 1.));
+        assert!(close(all.lower, 0.72246));
+        let half = Estimate::proportion(5, 10, 1.96);
+        assert!(close(half.lower, 0.23659) && close(half.upper, 0.76341));
+        assert_eq!(Estimate::proportion(0, 0, 1.96), Estimate { mean: 0., lower: 0., upper: 1., samples: 0 });
+    }
+
+    #[test]
+    fn normal_interval_of_a_mean() {
+        let estimate = Estimate::mean(&[1., 2., 3.], 2.);
+        assert_eq!(estimate.mean, 2.);
+        assert!(close(estimate.upper - estimate.mean, 1.1547) && close(estimate.mean - estimate.lower, 1.1547));
+        assert!(estimate.contains(3.) && !estimate.contains(3.2));
+        let single = Estimate::mean(&[4.], 1.96);
+        assert_eq!((single.lower, single.upper), (4., 4.));
+    }
+
+    #[test]
+    fn deterministic_episodes_agree_with_the_scheduler() {
+        let layout = WarehouseLayout::from_ascii("....\n....").unwrap();
+        let (mut racks, mut corridors, mut rotation_mapping) = warehouse_defaults();
+        let mut info = layout.make_info(&mut racks, &mut corridors, &mut rotation_mapping);
+        info.queue_point = (2, 0);
+        let dfa = TaskDfa::regeneration();
+        let mut policy = Policy::new(PolicyHeader::for_task(&info, &dfa));
+        let init = info.initial_state((0, 0), 0);
+        let mut next = init;
+        next.agent_position = (1, 0);
+        policy.insert(init, dfa.init, WarehouseAction::Forward).unwrap();
+        policy.insert(next, dfa.init, WarehouseAction::Forward).unwrap();
+        let warehouse: Robot<State, WarehouseWord> = Robot::make(5, Default::default());
+        let mut executor = Executor::new(policy, dfa, init);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let evaluation = MonteCarlo::new(3, 10)
+            .evaluate((0, 0), &warehouse, &mut executor, &info, &ConstantReward(1.), &mut rng)
+            .unwrap();
+        assert_eq!(evaluation.cost, Estimate { mean: 2., lower: 2., upper: 2., samples: 3 });
+        assert_eq!((evaluation.success.mean, evaluation.truncated), (1., 0));
+
+        // one step is not enough to reach the queue point
+        executor.reset(init);
+        let truncated = MonteCarlo::new(1, 1)
+            .evaluate((0, 0), &warehouse, &mut executor, &info, &ConstantReward(1.), &mut rng)
+            .unwrap();
+        assert_eq!((truncated.cost.mean, truncated.success.mean, truncated.truncated), (1., 0., 1));
+    }
+}
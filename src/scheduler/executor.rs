@@ -0,0 +1,99 @@
+use serde::{Serialize, Deserialize};
+use scpm::agent::Robot;
+use crate::env::warehouse::action::WarehouseAction;
+use crate::env::gym_env::{Env, most_likely_outcome};
+use crate::env::warehouse::high_fidelity_warehouse::{Info, State, WarehouseWord};
+use crate::error::Error;
+use crate::env::warehouse::tasks::TaskDfa;
+use crate::scheduler::policy::Policy;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExecutionStatus {
+    Running,
+    /// The task DFA has accepted
+    Complete,
+    /// The task DFA has rejected
+    Failed,
+    /// The policy has no action for the current robot state and DFA state
+    NoAction
+}
+
+/// Drives a robot from a synthesised policy. The executor tracks the DFA state of the task
+/// alongside the robot state: each observed robot state advances the DFA on its word, and the
+/// next action is looked up for the pair.
+pub struct Executor {
+    pub policy: Policy,
+    pub task: TaskDfa,
+    pub state: State,
+    pub q: i32
+}
+
+impl Executor {
+    /// An executor for a robot in `init`, with the DFA in its initial state
+    pub fn new(policy: Policy, task: TaskDfa, init: State) -> Executor {
+        Executor { policy, q: task.init, task, state: init }
+    }
+
+    /// Record the robot state observed after an action and advance the DFA
+    pub fn observe(&mut self, state: State, info: &Info) -> i32 {
+        self.state = state;
+        self.q = (self.task.next)(self.q, &WarehouseWord::from_state(&state, info), info);
+        self.q
+    }
+
+    /// The action for the current robot state and DFA state
+    pub fn action(&self) -> Option<WarehouseAction> {
+        match self.status() {
+            ExecutionStatus::Running => { self.policy.action(&self.state, self.q) }
+            _ => { None }
+        }
+    }
+
+    /// Observe `state` and return the next action, `None` once the task has finished or the policy
+    /// has no action
+    pub fn act(&mut self, state: State, info: &Info) -> Option<WarehouseAction> {
+        self.observe(state, info);
+        self.action()
+    }
+
+    pub fn status(&self) -> ExecutionStatus {
+        if self.task.is_complete(self.q) {
+            ExecutionStatus::Complete
+        } else if self.task.is_rejecting(self.q) {
+            ExecutionStatus::Failed
+        } else if self.policy.action(&self.state, self.q).is_none() {
+            ExecutionStatus::NoAction
+        } else {
+            ExecutionStatus::Running
+        }
+    }
+
+    /// Follow the most likely outcome of every action from the current state until the task
+    /// finishes, the policy has no action or `max_steps` actions have been taken. Returns the states
+    /// visited, starting with the current state.
+    pub fn greedy_trajectory(
+        &mut self,
+        warehouse: &Robot<State, WarehouseWord>,
+        info: &Info,
+        max_steps: usize
+    ) -> Result<Vec<State>, Error> {
+        let mut trajectory: Vec<State> = vec![self.state];
+        for _ in 0..max_steps {
+            let action = match self.action() {
+                Some(a) => { a }
+                None => { break }
+            };
+            let outcomes = warehouse.step(&self.state, action.index(), info)?;
+            let next = most_likely_outcome(&outcomes)?.0;
+            self.observe(next, info);
+            trajectory.push(next);
+        }
+        Ok(trajectory)
+    }
+
+    /// Start the policy again from `init`
+    pub fn reset(&mut self, init: State) {
+        self.state = init;
+        self.q = self.task.init;
+    }
+}
@@ -0,0 +1,159 @@
+use std::io::Write;
+use serde::{Serialize, Deserialize};
+use crate::env::warehouse::action::{Heading, WarehouseAction};
+use crate::env::warehouse::high_fidelity_warehouse::{Info, Point};
+use crate::env::warehouse::tasks::TaskDfa;
+use crate::error::Error;
+use crate::scheduler::policy::Policy;
+
+/*
+The JSON Lines policy export, one JSON object per line, each with a "type":
+header     the first line: the schema version, the action and heading names indexed by their
+           integers, the layout and the task allocation
+scheduler  starts the entries of a scheduler: the agent, the task (null for a regeneration
+           scheduler) and the shape of its task DFA
+entry      the action of the last scheduler in one robot state and DFA state
+Points are [x, y] with y pointing down. Fields are only ever added to the schema, anything that
+changes or removes a field bumps EXPORT_SCHEMA_VERSION.
+*/
+
+pub const EXPORT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExportLayout {
+    pub width: usize,
+    pub height: usize,
+    pub racks: Vec<Point>,
+    pub inventory_racks: Vec<Point>,
+    pub feed_points: Vec<Point>,
+    pub queue_points: Vec<Point>,
+    pub agent_starts: Vec<Point>,
+    pub chargers: Vec<Point>,
+    pub obstacles: Vec<Point>,
+    pub walls: Vec<Point>
+}
+
+impl ExportLayout {
+    pub fn from_info(info: &Info) -> ExportLayout {
+        ExportLayout {
+            width: info.width,
+            height: info.height,
+            racks: info.rack_positions.to_vec(),
+            inventory_racks: info.inventory_racks.to_vec(),
+            feed_points: info.feed_points.to_vec(),
+            queue_points: info.queue_points.to_vec(),
+            agent_starts: info.agent_start_positions.to_vec(),
+            chargers: info.charger_positions.to_vec(),
+            obstacles: info.obstacle_positions.to_vec(),
+            walls: info.wall_positions.to_vec()
+        }
+    }
+}
+
+/// A task of the allocation with the rack to replenish and the feed point to carry its pod to
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExportAllocation {
+    pub task: usize,
+    pub agent: usize,
+    pub rack: Point,
+    pub feed: Point
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExportDfa {
+    pub init: i32,
+    pub states: i32,
+    pub accepting: Vec<i32>,
+    pub rejecting: Vec<i32>
+}
+
+impl From<&TaskDfa> for ExportDfa {
+    fn from(dfa: &TaskDfa) -> Self {
+        ExportDfa {
+            init: dfa.init,
+            states: dfa.num_states,
+            accepting: dfa.accepting.to_vec(),
+            rejecting: dfa.rejecting.to_vec()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExportRecord {
+    Header {
+        schema: u32,
+        actions: Vec<String>,
+        headings: Vec<String>,
+        layout: ExportLayout,
+        allocations: Vec<ExportAllocation>
+    },
+    Scheduler {
+        agent: usize,
+        task: Option<usize>,
+        dfa: ExportDfa,
+        entries: usize
+    },
+    Entry {
+        x: i32,
+        y: i32,
+        heading: u8,
+        carrying: u8,
+        packs: Vec<Point>,
+        empty_racks: Vec<Point>,
+        battery: u8,
+        q: i32,
+        action: i32,
+        action_name: String
+    }
+}
+
+/// Writes the JSON Lines export: the header when created, then every scheduler passed to
+/// `write_policy`
+pub struct PolicyExport<W: Write> {
+    writer: W
+}
+
+impl<W: Write> PolicyExport<W> {
+    pub fn new(writer: W, layout: ExportLayout, allocations: Vec<ExportAllocation>) -> Result<PolicyExport<W>, Error> {
+        let mut export = PolicyExport { writer };
+        export.write_record(&ExportRecord::Header {
+            schema: EXPORT_SCHEMA_VERSION,
+            actions: WarehouseAction::ALL.iter().map(|a| a.name().to_string()).collect(),
+            headings: Heading::ALL.iter().map(|h| h.name().to_string()).collect(),
+            layout,
+            allocations
+        })?;
+        Ok(export)
+    }
+
+    fn write_record(&mut self, record: &ExportRecord) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn write_policy(&mut self, agent: usize, task: Option<usize>, dfa: &TaskDfa, policy: &Policy) -> Result<(), Error> {
+        self.write_record(&ExportRecord::Scheduler { agent, task, dfa: dfa.into(), entries: policy.len() })?;
+        for (state, q, action) in policy.entries() {
+            self.write_record(&ExportRecord::Entry {
+                x: state.agent_position.0,
+                y: state.agent_position.1,
+                heading: state.agent_dir,
+                carrying: state.carrying,
+                packs: state.floor_packs().collect(),
+                empty_racks: state.empty_rack_slots().collect(),
+                battery: state.battery,
+                q,
+                action: action.index(),
+                action_name: action.name().to_string()
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
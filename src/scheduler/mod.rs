@@ -0,0 +1,6 @@
+pub mod binary;
+pub mod evaluation;
+pub mod executor;
+pub mod export;
+pub mod policy;
+pub mod simulator;
@@ -0,0 +1,195 @@
+use std::fs;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::Path;
+use hashbrown::HashMap;
+use crate::artifacts::DecodedScheduler;
+use crate::env::warehouse::action::WarehouseAction;
+use crate::env::warehouse::high_fidelity_warehouse::{State, TaskActionPair};
+use crate::error::Error;
+use crate::scheduler::binary::{PolicyHeader, POLICY_MAGIC};
+
+/// The action table entry of a (state, DFA state) pair the scheduler never reaches
+const NO_ACTION: u8 = u8::MAX;
+
+/// A synthesised scheduler as a dense table with one action per robot state and DFA state, so
+/// that the action for an observation is a hash of the state and an index. Schedulers are stored
+/// in the binary format of `scheduler::binary`, the JSON of `create_decoded_sched_to_file` can be
+/// read and written for debugging.
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    pub header: PolicyHeader,
+    pub(crate) states: Vec<State>,
+    index: HashMap<State, usize>,
+    /// `header.dfa_states` entries per state in `states`
+    pub(crate) actions: Vec<u8>,
+    defined: usize
+}
+
+impl TaskActionPair {
+    /// The robot state of the entry
+    pub fn state(&self) -> Result<State, Error> {
+        let mut state = State {
+            agent_dir: self.agent_dir.index(),
+            agent_position: self.agent_position,
+            carrying: self.carrying,
+            battery: self.battery,
+            ..Default::default()
+        };
+        for p in self.packs.iter() {
+            if !state.add_pack(*p) {
+                return Err(Error::state_not_in_mapping(self))
+            }
+        }
+        for p in self.empty_racks.iter() {
+            if !state.empty_rack(*p) {
+                return Err(Error::state_not_in_mapping(self))
+            }
+        }
+        Ok(state)
+    }
+}
+
+impl Policy {
+    /// An empty policy for the layout and DFA of `header`
+    pub fn new(header: PolicyHeader) -> Policy {
+        Policy { header, ..Default::default() }
+    }
+
+    /// Set the action of the scheduler in robot state `state` and DFA state `q`
+    pub fn insert(&mut self, state: State, q: i32, action: WarehouseAction) -> Result<(), Error> {
+        let nq = self.header.dfa_states as usize;
+        if q < 0 || q as usize >= nq {
+            return Err(Error::state_not_in_mapping(&(state, q)))
+        }
+        let i = match self.index.get(&state) {
+            Some(i) => { *i }
+            None => {
+                let i = self.states.len();
+                self.states.push(state);
+                self.index.insert(state, i);
+                self.actions.resize(self.actions.len() + nq, NO_ACTION);
+                i
+            }
+        };
+        let entry = &mut self.actions[i * nq + q as usize];
+        if *entry == NO_ACTION {
+            self.defined += 1;
+        }
+        *entry = action.index() as u8;
+        Ok(())
+    }
+
+    /// The policy of a product MDP scheduler: `pi` holds the action of each product state,
+    /// `reverse_state_map` maps product states to (robot state index, q) and
+    /// `robot_reverse_state_map` maps robot state indices to states
+    pub fn from_product(
+        header: PolicyHeader,
+        pi: &[f64],
+        reverse_state_map: &HashMap<usize, (i32, i32)>,
+        robot_reverse_state_map: &HashMap<usize, State>
+    ) -> Result<Policy, Error> {
+        let mut policy = Policy::new(header);
+        for (sidx, a) in pi.iter().enumerate() {
+            let (s, q) = reverse_state_map.get(&sidx).ok_or_else(|| Error::state_not_in_mapping(&sidx))?;
+            let state = robot_reverse_state_map.get(&(*s as usize))
+                .ok_or_else(|| Error::state_not_in_mapping(s))?;
+            policy.insert(*state, *q, WarehouseAction::try_from(*a as i32)?)?;
+        }
+        Ok(policy)
+    }
+
+    /// A policy from a decoded scheduler, the layout of the header is unknown
+    pub fn from_decoded(scheduler: &DecodedScheduler) -> Result<Policy, Error> {
+        let pairs: Vec<&TaskActionPair> = scheduler.values().flat_map(|dirs| dirs.values()).flatten().collect();
+        let nq = pairs.iter().map(|p| p.q + 1).max().unwrap_or(0).max(0);
+        let mut policy = Policy::new(PolicyHeader { dfa_states: nq as u32, ..Default::default() });
+        for pair in pairs {
+            policy.insert(pair.state()?, pair.q, pair.action)?;
+        }
+        Ok(policy)
+    }
+
+    /// The scheduler grouped by agent position and direction, as written by
+    /// `create_decoded_sched_to_file`
+    pub fn to_decoded(&self) -> Result<DecodedScheduler, Error> {
+        let mut scheduler: DecodedScheduler = std::collections::HashMap::new();
+        for (state, q, action) in self.entries() {
+            scheduler.entry(format!("{:?}", state.agent_position))
+                .or_default()
+                .entry(format!("{}", state.agent_dir))
+                .or_default()
+                .push(TaskActionPair::new(&state, action, q)?);
+        }
+        Ok(scheduler)
+    }
+
+    /// Load a scheduler file, either in the binary format or the JSON debug format
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Policy, Error> {
+        let mut reader = BufReader::new(fs::File::open(path)?);
+        // a file shorter than the magic is not binary
+        let mut magic: Vec<u8> = Vec::with_capacity(POLICY_MAGIC.len());
+        (&mut reader).take(POLICY_MAGIC.len() as u64).read_to_end(&mut magic)?;
+        let reader = (&magic[..]).chain(reader);
+        if magic == POLICY_MAGIC {
+            Policy::read_binary(reader)
+        } else {
+            let scheduler: DecodedScheduler = serde_json::from_reader(reader)?;
+            Policy::from_decoded(&scheduler)
+        }
+    }
+
+    /// Write the scheduler in the binary format
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.write_binary(BufWriter::new(fs::File::create(path)?))
+    }
+
+    /// Write the scheduler as JSON for debugging
+    pub fn to_json_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        serde_json::to_writer_pretty(BufWriter::new(fs::File::create(path)?), &self.to_decoded()?)?;
+        Ok(())
+    }
+
+    /// The action of the scheduler in robot state `state` and DFA state `q`, `None` if the
+    /// scheduler never reaches the pair
+    pub fn action(&self, state: &State, q: i32) -> Option<WarehouseAction> {
+        let nq = self.header.dfa_states as usize;
+        if q < 0 || q as usize >= nq {
+            return None
+        }
+        let i = *self.index.get(state)?;
+        WarehouseAction::from_index(self.actions[i * nq + q as usize] as i32)
+    }
+
+    /// Every (state, q, action) entry of the scheduler
+    pub fn entries(&self) -> impl Iterator<Item=(State, i32, WarehouseAction)> + '_ {
+        let nq = self.header.dfa_states as usize;
+        self.actions.iter().enumerate().filter_map(move |(i, a)| {
+            WarehouseAction::from_index(*a as i32).map(|a| (self.states[i / nq], (i % nq) as i32, a))
+        })
+    }
+
+    /// A policy from a dense table of `header.dfa_states` actions per state, every entry must be
+    /// an action index or the no action sentinel, `Error::UnknownAction` otherwise
+    pub(crate) fn from_table(header: PolicyHeader, states: Vec<State>, actions: Vec<u8>) -> Result<Policy, Error> {
+        let mut policy = Policy::new(header);
+        let nq = header.dfa_states as usize;
+        for (i, state) in states.iter().enumerate() {
+            for q in 0..nq {
+                let a = actions[i * nq + q];
+                if a == NO_ACTION {
+                    continue
+                }
+                policy.insert(*state, q as i32, WarehouseAction::try_from(a as i32)?)?;
+            }
+        }
+        Ok(policy)
+    }
+
+    pub fn len(&self) -> usize {
+        self.defined
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defined == 0
+    }
+}
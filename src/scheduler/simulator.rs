@@ -0,0 +1,373 @@
+use std::collections::VecDeque;
+use rand::Rng;
+use scpm::agent::Robot;
+use serde::{Serialize, Deserialize};
+use crate::env::reward::RewardModel;
+use crate::env::gym_env::{Env, sample_outcome};
+use crate::env::warehouse::high_fidelity_warehouse::{Info, Point, State, WarehouseWord};
+use crate::error::Error;
+use crate::scheduler::executor::{ExecutionStatus, Executor};
+
+/// A scheduler queued for an agent, with the task parameters its DFA reads from `Info`
+pub struct Job {
+    pub task: Option<usize>, // None for a regeneration scheduler
+    pub executor: Executor,
+    pub lookup_rack: usize,
+    pub feed_option: usize,
+    pub queue_point: Point
+}
+
+impl Job {
+    fn configure(&self, info: &mut Info) {
+        info.lookup_rack = self.lookup_rack;
+        info.feed_option = self.feed_option;
+        info.queue_point = self.queue_point;
+    }
+}
+
+/// An agent of the simulation and the schedulers it executes in order, typically its allocated
+/// tasks followed by its regeneration scheduler
+pub struct SimAgent {
+    pub agent: usize,
+    pub state: State,
+    pub jobs: VecDeque<Job>,
+    started: Option<usize> // the step the current job started
+}
+
+impl SimAgent {
+    pub fn new(agent: usize, state: State, jobs: Vec<Job>) -> SimAgent {
+        SimAgent { agent, state, jobs: jobs.into(), started: None }
+    }
+
+    fn is_active(&self) -> bool {
+        !self.jobs.is_empty()
+    }
+}
+
+/// The outcome of one scheduler
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JobOutcome {
+    pub agent: usize,
+    pub task: Option<usize>,
+    pub status: ExecutionStatus,
+    pub start: usize,
+    pub end: usize,
+    pub final_position: Point
+}
+
+/// Two agents which tried to enter the same cell, or to swap cells, in the same step. The agent
+/// without priority waits where it is.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Collision {
+    Vertex { step: usize, agents: (usize, usize), position: Point },
+    Swap { step: usize, agents: (usize, usize), positions: (Point, Point) }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SimulationReport {
+    /// The step at which the last agent finished its schedulers
+    pub makespan: usize,
+    pub steps: usize,
+    /// The scalarised reward accumulated by each agent over the actions it performed
+    pub agent_costs: Vec<f64>,
+    pub outcomes: Vec<JobOutcome>,
+    pub collisions: Vec<Collision>,
+    /// The state of each agent before the first step and after every step
+    pub trajectories: Vec<Vec<State>>,
+    /// The step at which the agents were found to be deadlocked
+    pub deadlock: Option<usize>
+}
+
+impl SimulationReport {
+    /// The number of task schedulers which finished with `status`
+    pub fn tasks_with_status(&self, status: ExecutionStatus) -> usize {
+        self.outcomes.iter().filter(|o| o.task.is_some() && o.status == status).count()
+    }
+
+    pub fn tasks_completed(&self) -> usize {
+        self.tasks_with_status(ExecutionStatus::Complete)
+    }
+
+    /// The tasks whose DFA rejected
+    pub fn tasks_failed(&self) -> usize {
+        self.tasks_with_status(ExecutionStatus::Failed)
+    }
+
+    /// The tasks still running when the step limit was reached or the agents deadlocked
+    pub fn tasks_unfinished(&self) -> usize {
+        self.tasks_with_status(ExecutionStatus::Running)
+    }
+
+    /// The tasks whose scheduler had no action for the state the agent reached
+    pub fn tasks_without_action(&self) -> usize {
+        self.tasks_with_status(ExecutionStatus::NoAction)
+    }
+}
+
+/// Steps every agent simultaneously on a single warehouse. Each agent's action outcome is sampled
+/// from `Env::step`, then moves are checked against each other: an agent which stays in its
+/// cell keeps it, otherwise the agent with the lowest index enters a contested cell, and agents
+/// may not swap cells. Agents which lose a conflict wait for the next step. The agents are
+/// deadlocked if no agent can move for `deadlock_steps` consecutive steps while some are blocked.
+pub struct MultiAgentSimulator {
+    pub agents: Vec<SimAgent>,
+    pub max_steps: usize,
+    pub deadlock_steps: usize
+}
+
+impl MultiAgentSimulator {
+    pub fn new(agents: Vec<SimAgent>, max_steps: usize, deadlock_steps: usize) -> MultiAgentSimulator {
+        MultiAgentSimulator { agents, max_steps, deadlock_steps }
+    }
+
+    pub fn run<R: Rng>(
+        &mut self,
+        warehouse: &Robot<State, WarehouseWord>,
+        info: &mut Info,
+        rewards: &dyn RewardModel<State>,
+        rng: &mut R
+    ) -> Result<SimulationReport, Error> {
+        let n = self.agents.len();
+        let mut report = SimulationReport {
+            makespan: 0,
+            steps: 0,
+            agent_costs: vec![0.; n],
+            outcomes: Vec::new(),
+            collisions: Vec::new(),
+            trajectories: self.agents.iter().map(|a| vec![a.state]).collect(),
+            deadlock: None
+        };
+        let mut stalled: usize = 0;
+        for step in 0..self.max_steps {
+            self.finish_jobs(step, &mut report);
+            if self.agents.iter().all(|a| !a.is_active()) {
+                break
+            }
+            report.steps = step + 1;
+            // each agent proposes its next state, idle agents stay where they are
+            let mut proposals: Vec<Option<(State, f64)>> = Vec::with_capacity(n);
+            for agent in self.agents.iter() {
+                let job = match agent.jobs.front() {
+                    Some(job) => { job }
+                    None => { proposals.push(None); continue }
+                };
+                job.configure(info);
+                let action = match job.executor.action() {
+                    Some(a) => { a }
+                    None => { proposals.push(None); continue }
+                };
+                let outcomes = warehouse.step(&agent.state, action.index(), info)?;
+                let (next, _, _) = sample_outcome(&outcomes, rng)?;
+                let cost = rewards.scalarise(&rewards.reward(&agent.state, action.index(), info)[..]);
+                proposals.push(Some((*next, cost)));
+            }
+            let current: Vec<Point> = self.agents.iter().map(|a| a.state.agent_position).collect();
+            let mut target: Vec<Point> = proposals.iter()
+                .zip(current.iter())
+                .map(|(p, c)| p.as_ref().map_or(*c, |(s, _)| s.agent_position))
+                .collect();
+            let blocked = resolve_conflicts(step, &current, &mut target, &mut report.collisions);
+            // apply the moves which survived conflict resolution
+            let mut moved = false;
+            for (i, proposal) in proposals.into_iter().enumerate() {
+                let (next, cost) = match proposal {
+                    Some(p) if !blocked[i] => { p }
+                    _ => { continue }
+                };
+                let agent = &mut self.agents[i];
+                moved |= next != agent.state;
+                agent.state = next;
+                report.agent_costs[i] += cost;
+                if let Some(job) = agent.jobs.front_mut() {
+                    job.configure(info);
+                    job.executor.observe(next, info);
+                }
+            }
+            for (i, agent) in self.agents.iter().enumerate() {
+                report.trajectories[i].push(agent.state);
+            }
+            if !moved && blocked.iter().any(|b| *b) {
+                stalled += 1;
+                if stalled >= self.deadlock_steps {
+                    report.deadlock = Some(step);
+                    break
+                }
+            } else {
+                stalled = 0;
+            }
+        }
+        // schedulers which did not finish in time are reported as they stand
+        let end = report.steps;
+        self.finish_jobs(end, &mut report);
+        for agent in self.agents.iter_mut() {
+            while let Some(job) = agent.jobs.pop_front() {
+                report.outcomes.push(JobOutcome {
+                    agent: agent.agent,
+                    task: job.task,
+                    status: job.executor.status(),
+                    start: agent.started.take().unwrap_or(end),
+                    end,
+                    final_position: agent.state.agent_position
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    /// Record the jobs which have finished and start the next job of each agent from where the
+    /// agent is
+    fn finish_jobs(&mut self, step: usize, report: &mut SimulationReport) {
+        for agent in self.agents.iter_mut() {
+            while let Some(job) = agent.jobs.front_mut() {
+                if agent.started.is_none() {
+                    agent.started = Some(step);
+                    job.executor.reset(agent.state);
+                }
+                let status = job.executor.status();
+                if status == ExecutionStatus::Running {
+                    break
+                }
+                report.outcomes.push(JobOutcome {
+                    agent: agent.agent,
+                    task: job.task,
+                    status,
+                    start: agent.started.take().unwrap_or(step),
+                    end: step,
+                    final_position: agent.state.agent_position
+                });
+                report.makespan = report.makespan.max(step);
+                agent.jobs.pop_front();
+            }
+        }
+    }
+}
+
+/// Block the moves of agents which lose a conflict, until the remaining moves are conflict free.
+/// Returns which agents are blocked.
+fn resolve_conflicts(
+    step: usize,
+    current: &[Point],
+    target: &mut [Point],
+    collisions: &mut Vec<Collision>
+) -> Vec<bool> {
+    let n = current.len();
+    let mut blocked = vec![false; n];
+    loop {
+        let mut changed = false;
+        for i in 0..n {
+            for j in (i + 1)..n {
+                if target[i] == target[j] && (target[i] != current[i] || target[j] != current[j]) {
+                    // an agent staying in its cell keeps it, otherwise the lower index has priority
+                    let loser = if target[j] == current[j] { i } else { j };
+                    collisions.push(Collision::Vertex { step, agents: (i, j), position: target[i] });
+                    blocked[loser] = true;
+                    target[loser] = current[loser];
+                    changed = true;
+                } else if target[i] == current[j] && target[j] == current[i] && target[i] != current[i] {
+                    collisions.push(Collision::Swap { step, agents: (i, j), positions: (current[i], current[j]) });
+                    blocked[i] = true;
+                    blocked[j] = true;
+                    target[i] = current[i];
+                    target[j] = current[j];
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            return blocked
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::env::reward::ConstantReward;
+    use crate::env::warehouse::action::WarehouseAction;
+    use crate::env::warehouse::high_fidelity_warehouse::warehouse_defaults;
+    use crate::env::warehouse::layout::WarehouseLayout;
+    use crate::env::warehouse::tasks::TaskDfa;
+    use crate::scheduler::binary::PolicyHeader;
+    use crate::scheduler::policy::Policy;
+    use super::*;
+
+    #[test]
+    fn vertex_conflicts_go_to_the_agent_staying_or_the_lower_index() {
+        let mut collisions = Vec::new();
+        // two agents entering one cell, the lower index enters
+        let mut target = vec![(1, 0), (1, 0)];
+        assert_eq!(resolve_conflicts(0, &[(0, 0), (2, 0)], &mut target, &mut collisions), vec![false, true]);
+        assert_eq!(target, vec![(1, 0), (2, 0)]);
+        // an agent staying in its cell keeps it, whatever its index
+        let mut target = vec![(1, 0), (1, 0)];
+        assert_eq!(resolve_conflicts(1, &[(0, 0), (1, 0)], &mut target, &mut collisions), vec![true, false]);
+        assert_eq!(target, vec![(0, 0), (1, 0)]);
+        assert_eq!(collisions, vec![
+            Collision::Vertex { step: 0, agents: (0, 1), position: (1, 0) },
+            Collision::Vertex { step: 1, agents: (0, 1), position: (1, 0) }
+        ]);
+    }
+
+    #[test]
+    fn agents_may_not_swap_cells() {
+        let mut collisions = Vec::new();
+        let mut target = vec![(1, 0), (0, 0), (3, 0)];
+        let blocked = resolve_conflicts(4, &[(0, 0), (1, 0), (2, 0)], &mut target, &mut collisions);
+        assert_eq!(blocked, vec![true, true, false]);
+        assert_eq!(target, vec![(0, 0), (1, 0), (3, 0)]);
+        assert_eq!(collisions, vec![Collision::Swap { step: 4, agents: (0, 1), positions: ((0, 0), (1, 0)) }]);
+    }
+
+    #[test]
+    fn agents_facing_each_other_deadlock() {
+        let layout = WarehouseLayout::from_ascii("....").unwrap();
+        let (mut racks, mut corridors, mut rotation_mapping) = warehouse_defaults();
+        let mut info = layout.make_info(&mut racks, &mut corridors, &mut rotation_mapping);
+        let dfa = TaskDfa::regeneration();
+        // each agent drives forward towards the other one's queue point
+        let agent = |i: usize, start: Point, heading: u8, queue_point: Point| {
+            let state = info.initial_state(start, heading);
+            let mut policy = Policy::new(PolicyHeader::for_task(&info, &dfa));
+            policy.insert(state, dfa.init, WarehouseAction::Forward).unwrap();
+            let executor = Executor::new(policy, TaskDfa::regeneration(), state);
+            let job = Job { task: Some(i), executor, lookup_rack: 0, feed_option: 0, queue_point };
+            SimAgent::new(i, state, vec![job])
+        };
+        let agents = vec![agent(0, (1, 0), 0, (3, 0)), agent(1, (2, 0), 2, (0, 0))];
+        let warehouse: Robot<State, WarehouseWord> = Robot::make(5, Default::default());
+        let mut simulator = MultiAgentSimulator::new(agents, 20, 3);
+        let report = simulator.run(&warehouse, &mut info, &ConstantReward(1.), &mut StdRng::seed_from_u64(0))
+            .unwrap();
+        assert_eq!(report.deadlock, Some(2));
+        assert_eq!(report.collisions.len(), 3);
+        assert_eq!(report.tasks_unfinished(), 2);
+        assert_eq!(report.agent_costs, vec![0., 0.]);
+    }
+
+    #[test]
+    fn truncated_and_stuck_tasks_are_not_failures() {
+        let outcome = |task: Option<usize>, status: ExecutionStatus| JobOutcome {
+            agent: 0, task, status, start: 0, end: 1, final_position: (0, 0)
+        };
+        let report = SimulationReport {
+            makespan: 1,
+            steps: 1,
+            agent_costs: vec![0.],
+            outcomes: vec![
+                outcome(Some(0), ExecutionStatus::Complete),
+                outcome(Some(1), ExecutionStatus::Failed),
+                outcome(Some(2), ExecutionStatus::Running),
+                outcome(Some(3), ExecutionStatus::NoAction),
+                outcome(None, ExecutionStatus::Failed)
+            ],
+            collisions: Vec::new(),
+            trajectories: Vec::new(),
+            deadlock: None
+        };
+        assert_eq!(report.tasks_completed(), 1);
+        assert_eq!(report.tasks_failed(), 1);
+        assert_eq!(report.tasks_unfinished(), 1);
+        assert_eq!(report.tasks_without_action(), 1);
+    }
+}